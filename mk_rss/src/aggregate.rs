@@ -0,0 +1,93 @@
+use reqwest::Url;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::feed_request::FeedRequest;
+use super::xml;
+
+#[derive(Debug, PartialEq)]
+pub struct AggregateRequest {
+    /// The name of the aggregated feed
+    pub name: String,
+
+    /// The link of the aggregated feed, e.g. a team page
+    pub url: Url,
+
+    /// The feeds to merge. Each is fetched concurrently.
+    pub feeds: Vec<FeedRequest>,
+
+    /// How each item records which feed it came from
+    pub source_tag: SourceTag,
+
+    /// The maximum number of items to return across all feeds
+    pub max_items: usize,
+}
+
+/// How an aggregated item records the feed it came from.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SourceTag {
+    /// A `<category>` named after the feed, which most readers can filter by
+    Category,
+
+    /// An RSS `<source>` element naming the feed
+    Source,
+}
+
+/// The feed an aggregated item came from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ItemSource {
+    pub name: String,
+    pub url: Url,
+    pub tag: SourceTag,
+}
+
+impl AggregateRequest {
+    pub fn new(name: &str, url: Url, feeds: Vec<FeedRequest>) -> Self {
+        AggregateRequest {
+            name: name.to_string(),
+            url,
+            feeds,
+            source_tag: SourceTag::Category,
+            max_items: 30,
+        }
+    }
+}
+
+impl ItemSource {
+    pub fn to_rss_xml(&self) -> String {
+        match self.tag {
+            SourceTag::Category => format!("<category domain=\"{}\">{}</category>", xml::escape(self.url.as_str()), xml::escape(&self.name)),
+            SourceTag::Source => format!("<source url=\"{}\">{}</source>", xml::escape(self.url.as_str()), xml::escape(&self.name)),
+        }
+    }
+}
+
+impl TryFrom<&str> for SourceTag {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "category" => Ok(SourceTag::Category),
+            "source" => Ok(SourceTag::Source),
+            _ => Err(anyhow::anyhow!("{} is not a valid source tag (valid source tags are 'category' and 'source')", value))
+        }
+    }
+}
+
+impl FromStr for SourceTag {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+impl fmt::Display for SourceTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SourceTag::Category => write!(f, "category"),
+            SourceTag::Source => write!(f, "source"),
+        }
+    }
+}
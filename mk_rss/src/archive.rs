@@ -0,0 +1,392 @@
+use anyhow::Context;
+use chrono::{DateTime, Local, TimeZone};
+use reqwest::Url;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use sha2::{Digest, Sha256};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::feed::{Feed, FeedItem};
+use super::guid::Guid;
+use super::media::Enclosure;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS items (
+        feed TEXT NOT NULL,
+        guid TEXT NOT NULL,
+        is_perma_link INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        url TEXT NOT NULL,
+        pub_date INTEGER NOT NULL,
+        description TEXT,
+        author TEXT,
+        categories TEXT NOT NULL,
+        image TEXT,
+        enclosure_url TEXT,
+        enclosure_type TEXT,
+        enclosure_length INTEGER,
+        first_seen INTEGER NOT NULL,
+        feed_name TEXT,
+        PRIMARY KEY (feed, guid)
+    );
+
+    CREATE INDEX IF NOT EXISTS items_by_date ON items (feed, pub_date);
+";
+
+/// `feed_name` was added after the first archives were written, which keyed feeds by their name
+const ADD_FEED_NAME: &str = "ALTER TABLE items ADD COLUMN feed_name TEXT";
+
+const ITEM_COLUMNS: &str = "feed, guid, is_perma_link, title, url, pub_date, description, author, categories, image, enclosure_url, enclosure_type, enclosure_length, first_seen, feed_name";
+
+/// Every item each feed has ever had, so a feed can keep its history after items drop off the page.
+///
+/// Feeds are identified by a key derived from their whole definition (see `Archive::key`), so two
+/// feeds that share a name never share an archive. The name is kept alongside for listing items.
+pub struct Archive {
+    connection: Connection,
+}
+
+/// An item as it was stored in the archive
+#[derive(Debug)]
+pub struct ArchivedItem {
+    /// The key of the feed the item belongs to
+    pub feed: String,
+
+    /// The name of the feed the item belongs to
+    pub feed_name: String,
+    pub item: FeedItem,
+
+    /// When the item was first archived
+    pub first_seen: DateTime<Local>,
+}
+
+impl Archive {
+    /// Open (or create) the archive at `path`
+    pub fn open(path: &Path) -> anyhow::Result<Archive> {
+        let connection = Connection::open(path).context(format!("failed to open archive: {:?}", path))?;
+        Archive::with_connection(connection)
+    }
+
+    /// An archive that only lasts as long as it's open
+    pub fn open_in_memory() -> anyhow::Result<Archive> {
+        Archive::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> anyhow::Result<Archive> {
+        connection.execute_batch(SCHEMA)?;
+
+        let has_feed_name = connection
+            .prepare("SELECT 1 FROM pragma_table_info('items') WHERE name = 'feed_name'")?
+            .exists([])?;
+        if !has_feed_name {
+            connection.execute_batch(ADD_FEED_NAME)?;
+        }
+
+        Ok(Archive { connection })
+    }
+
+    /// The key of the feed defined by the query parameters `pairs`, e.g. from `FeedRequestBuilder::to_query_pairs`.
+    ///
+    /// Parameters are sorted by name so the order of the query string doesn't matter, but repeated
+    /// parameters keep their order since it does (e.g. title rewrites are applied in order).
+    pub fn key(mut pairs: Vec<(String, String)>) -> String {
+        pairs.sort_by(|(name, _), (other_name, _)| name.cmp(other_name));
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(pairs)
+            .finish();
+
+        format!("{:x}", Sha256::digest(query.as_bytes()))
+    }
+
+    /// Archive every item in `feed` under `key`, returning how many we hadn't seen before.
+    ///
+    /// Items we've already archived keep their original publish date, since dates we inferred
+    /// from the order of the page shift every time the page changes.
+    pub fn store(&mut self, key: &str, feed: &Feed, now: DateTime<Local>) -> anyhow::Result<usize> {
+        let transaction = self.connection.transaction()?;
+        let mut archived = 0;
+
+        {
+            let mut exists = transaction.prepare("SELECT 1 FROM items WHERE feed = ?1 AND guid = ?2")?;
+            let mut insert = transaction.prepare(&format!(
+                "INSERT INTO items ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+                ITEM_COLUMNS
+            ))?;
+            let mut update = transaction.prepare(
+                "UPDATE items SET title = ?3, url = ?4, description = ?5, author = ?6, categories = ?7, image = ?8,
+                    enclosure_url = ?9, enclosure_type = ?10, enclosure_length = ?11, feed_name = ?12
+                 WHERE feed = ?1 AND guid = ?2"
+            )?;
+
+            for item in &feed.items {
+                let categories = serde_json::to_string(&item.categories)?;
+                let image = item.image.as_ref().map(Url::as_str);
+                let enclosure_url = item.enclosure.as_ref().map(|enclosure| enclosure.url.as_str());
+                let enclosure_type = item.enclosure.as_ref().and_then(|enclosure| enclosure.mime_type.as_deref());
+                let enclosure_length = item.enclosure.as_ref().and_then(|enclosure| enclosure.length).map(|length| length as i64);
+
+                let already_archived = exists
+                    .query_row(params![key, item.guid.value], |_| Ok(()))
+                    .optional()?
+                    .is_some();
+
+                if already_archived {
+                    update.execute(params![
+                        key, item.guid.value, item.title, item.url.as_str(), item.description, item.author,
+                        categories, image, enclosure_url, enclosure_type, enclosure_length, feed.name
+                    ])?;
+                } else {
+                    insert.execute(params![
+                        key, item.guid.value, item.guid.is_perma_link, item.title, item.url.as_str(),
+                        item.pub_date.timestamp(), item.description, item.author, categories, image,
+                        enclosure_url, enclosure_type, enclosure_length, now.timestamp(), feed.name
+                    ])?;
+                    archived += 1;
+                }
+            }
+        }
+
+        transaction.commit()?;
+        Ok(archived)
+    }
+
+    /// Archive `feed` under `key`, then fill it with archived items until it has `max_items`, newest first
+    pub fn extend_feed(&mut self, key: &str, feed: Feed, max_items: usize, now: DateTime<Local>) -> anyhow::Result<Feed> {
+        self.store(key, &feed, now)?;
+
+        let current_guids: HashSet<String> = feed.items.iter().map(|item| item.guid.value.clone()).collect();
+        let archived_items = self.query(
+            "WHERE feed = ?1 ORDER BY pub_date DESC LIMIT ?2",
+            params![key, max_items as i64],
+        )?;
+
+        let mut items = feed.items;
+        items.extend(
+            archived_items
+                .into_iter()
+                .map(|archived| archived.item)
+                .filter(|item| !current_guids.contains(&item.guid.value))
+        );
+
+        items.sort_by_key(|item| Reverse(item.pub_date));
+        items.truncate(max_items);
+
+        Ok(Feed { items, ..feed })
+    }
+
+    /// How many complete archive pages of `page_size` items the feed with `key` has
+    pub fn archive_page_count(&self, key: &str, page_size: usize) -> anyhow::Result<usize> {
+        let item_count: i64 = self.connection.query_row("SELECT COUNT(*) FROM items WHERE feed = ?1", params![key], |row| row.get(0))?;
+        Ok(item_count as usize / page_size)
+    }
+
+    /// Page `page` of the archive of the feed with `key`, newest item first.
+    ///
+    /// Pages count from 1 and hold the items in the order we first saw them, so that once a page is
+    /// complete it never changes, which is what RFC 5005 expects of an archive document.
+    pub fn archive_page(&self, key: &str, page: usize, page_size: usize) -> anyhow::Result<Vec<FeedItem>> {
+        if page == 0 || page > self.archive_page_count(key, page_size)? {
+            return Err(anyhow::anyhow!("There is no archive page {}", page));
+        }
+
+        let archived_items = self.query(
+            "WHERE feed = ?1 ORDER BY first_seen, pub_date, guid LIMIT ?2 OFFSET ?3",
+            params![key, page_size as i64, ((page - 1) * page_size) as i64],
+        )?;
+
+        Ok(archived_items.into_iter().rev().map(|archived| archived.item).collect())
+    }
+
+    /// The items of the feed with `key` that aren't on a complete archive page yet, newest first.
+    ///
+    /// Together with the archive pages these cover every item, so the current feed should include them.
+    pub fn incomplete_page(&self, key: &str, page_size: usize) -> anyhow::Result<Vec<FeedItem>> {
+        let page_count = self.archive_page_count(key, page_size)?;
+        let archived_items = self.query(
+            "WHERE feed = ?1 ORDER BY first_seen, pub_date, guid LIMIT -1 OFFSET ?2",
+            params![key, (page_count * page_size) as i64],
+        )?;
+
+        Ok(archived_items.into_iter().rev().map(|archived| archived.item).collect())
+    }
+
+    /// The items published since `since`, newest first. Only items from feeds named `feed_name` if given.
+    pub fn items_since(&self, feed_name: Option<&str>, since: DateTime<Local>) -> anyhow::Result<Vec<ArchivedItem>> {
+        self.query(
+            "WHERE (?1 IS NULL OR COALESCE(feed_name, feed) = ?1) AND pub_date >= ?2 ORDER BY pub_date DESC",
+            params![feed_name, since.timestamp()],
+        )
+    }
+
+    /// The items whose title contains `text` (ignoring case), newest first. Only items from feeds named `feed_name` if given.
+    pub fn search(&self, feed_name: Option<&str>, text: &str) -> anyhow::Result<Vec<ArchivedItem>> {
+        let pattern = format!("%{}%", text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+        self.query(
+            "WHERE (?1 IS NULL OR COALESCE(feed_name, feed) = ?1) AND title LIKE ?2 ESCAPE '\\' ORDER BY pub_date DESC",
+            params![feed_name, pattern],
+        )
+    }
+
+    fn query(&self, clauses: &str, params: &[&dyn rusqlite::ToSql]) -> anyhow::Result<Vec<ArchivedItem>> {
+        let mut statement = self.connection.prepare(&format!("SELECT {} FROM items {}", ITEM_COLUMNS, clauses))?;
+        let rows = statement.query_map(params, archived_item)?;
+
+        // A database error fails the whole query, but a row we can't make sense of (e.g. a url edited
+        // by hand) is skipped rather than hiding the rest of the archive
+        let items = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(items.into_iter().flatten().collect())
+    }
+}
+
+fn archived_item(row: &Row) -> rusqlite::Result<Option<ArchivedItem>> {
+    let timestamp = |index: usize| -> rusqlite::Result<Option<DateTime<Local>>> {
+        Ok(Local.timestamp_opt(row.get(index)?, 0).single())
+    };
+
+    let url = match Url::parse(&row.get::<_, String>(4)?) {
+        Ok(url) => url,
+        Err(_) => return Ok(None),
+    };
+
+    let (pub_date, first_seen) = match (timestamp(5)?, timestamp(13)?) {
+        (Some(pub_date), Some(first_seen)) => (pub_date, first_seen),
+        _ => return Ok(None),
+    };
+
+    let categories: Vec<String> = serde_json::from_str(&row.get::<_, String>(8)?).unwrap_or_default();
+    let image = row.get::<_, Option<String>>(9)?.and_then(|image| Url::parse(&image).ok());
+    let enclosure = row
+        .get::<_, Option<String>>(10)?
+        .and_then(|enclosure_url| Url::parse(&enclosure_url).ok())
+        .map(|enclosure_url| -> rusqlite::Result<Enclosure> {
+            Ok(Enclosure {
+                url: enclosure_url,
+                mime_type: row.get(11)?,
+                length: row.get::<_, Option<i64>>(12)?.map(|length| length as u64),
+            })
+        })
+        .transpose()?;
+
+    let item = FeedItem {
+        title: row.get(3)?,
+        url,
+        pub_date,
+        description: row.get(6)?,
+        guid: Guid { value: row.get(1)?, is_perma_link: row.get(2)? },
+        image,
+        enclosure,
+        author: row.get(7)?,
+        categories,
+        source: None,
+    };
+
+    let feed: String = row.get(0)?;
+
+    // Items archived before we kept names were archived under their feed's name
+    let feed_name = row.get::<_, Option<String>>(14)?.unwrap_or_else(|| feed.clone());
+
+    Ok(Some(ArchivedItem { feed, feed_name, item, first_seen }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(items: &[(&str, u32)]) -> Feed {
+        let items = items
+            .iter()
+            .map(|(path, day)| FeedItem {
+                pub_date: Local.ymd(2021, 1, *day).and_hms(12, 0, 0),
+                categories: vec!["news".into()],
+                ..FeedItem::for_test(&format!("Story {}", path), &format!("https://example.com/{}", path))
+            })
+            .collect();
+
+        Feed::for_test("Example", items)
+    }
+
+    #[test]
+    pub fn extend_feed_should_keep_items_that_dropped_off_the_page() {
+        let mut archive = Archive::open_in_memory().unwrap();
+        let now = Local.ymd(2021, 1, 10).and_hms(12, 0, 0);
+
+        assert_eq!(archive.store("Example", &feed(&[("b", 2), ("a", 1)]), now).unwrap(), 2);
+
+        let extended = archive.extend_feed("Example", feed(&[("c", 3), ("b", 2)]), 30, now).unwrap();
+        let titles: Vec<&str> = extended.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, vec!["Story c", "Story b", "Story a"]);
+        assert_eq!(extended.items[2].categories, vec!["news".to_string()]);
+
+        let limited = archive.extend_feed("Example", feed(&[("c", 3)]), 2, now).unwrap();
+        assert_eq!(limited.items.len(), 2);
+    }
+
+    #[test]
+    pub fn items_since_and_search() {
+        let mut archive = Archive::open_in_memory().unwrap();
+        let now = Local.ymd(2021, 1, 10).and_hms(12, 0, 0);
+        archive.store("Example", &feed(&[("c", 3), ("b", 2), ("a_1", 1)]), now).unwrap();
+
+        let since = archive.items_since(Some("Example"), Local.ymd(2021, 1, 2).and_hms(0, 0, 0)).unwrap();
+        assert_eq!(since.len(), 2);
+
+        let found = archive.search(None, "y A_").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].item.title, "Story a_1");
+        assert!(archive.search(Some("Other"), "Story").unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn key_should_identify_the_feed_definition() {
+        let pairs = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+
+        let key = Archive::key(pairs(&[("name", "Example"), ("url", "https://example.com/"), ("item_selector", "article")]));
+
+        assert_eq!(key, Archive::key(pairs(&[("item_selector", "article"), ("name", "Example"), ("url", "https://example.com/")])));
+        assert_ne!(key, Archive::key(pairs(&[("name", "Example"), ("url", "https://attacker.example.com/"), ("item_selector", "article")])));
+    }
+
+    #[test]
+    pub fn items_should_be_listed_by_feed_name() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(&SCHEMA.replace("feed_name TEXT,", "")).unwrap();
+        connection.execute(
+            &format!("INSERT INTO items ({}) VALUES ('Old', 'old', 0, 'Old story', 'https://example.com/old', 0, NULL, NULL, '', NULL, NULL, NULL, NULL, 0)", ITEM_COLUMNS.replace(", feed_name", "")),
+            [],
+        ).unwrap();
+
+        let mut archive = Archive::with_connection(connection).unwrap();
+        let now = Local.ymd(2021, 1, 10).and_hms(12, 0, 0);
+        archive.store("0a1b2c", &feed(&[("a", 1)]), now).unwrap();
+
+        let found = archive.search(Some("Example"), "Story").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].feed.as_str(), found[0].feed_name.as_str()), ("0a1b2c", "Example"));
+
+        let old = archive.search(Some("Old"), "story").unwrap();
+        assert_eq!((old[0].feed.as_str(), old[0].feed_name.as_str()), ("Old", "Old"));
+    }
+
+    #[test]
+    pub fn archive_pages_should_not_change_once_complete() {
+        let mut archive = Archive::open_in_memory().unwrap();
+        archive.store("Example", &feed(&[("b", 2), ("a", 1)]), Local.ymd(2021, 1, 2).and_hms(12, 0, 0)).unwrap();
+        archive.store("Example", &feed(&[("c", 3), ("b", 2)]), Local.ymd(2021, 1, 3).and_hms(12, 0, 0)).unwrap();
+
+        let titles = |items: Vec<FeedItem>| items.into_iter().map(|item| item.title).collect::<Vec<String>>();
+
+        assert_eq!(archive.archive_page_count("Example", 2).unwrap(), 1);
+        assert!(archive.archive_page("Example", 2, 2).is_err());
+        assert_eq!(titles(archive.incomplete_page("Example", 2).unwrap()), vec!["Story c"]);
+
+        assert_eq!(titles(archive.archive_page("Example", 1, 2).unwrap()), vec!["Story b", "Story a"]);
+
+        archive.store("Example", &feed(&[("e", 5), ("d", 4)]), Local.ymd(2021, 1, 5).and_hms(12, 0, 0)).unwrap();
+        assert_eq!(titles(archive.archive_page("Example", 1, 2).unwrap()), vec!["Story b", "Story a"]);
+        assert_eq!(titles(archive.archive_page("Example", 2, 2).unwrap()), vec!["Story d", "Story c"]);
+        assert_eq!(titles(archive.incomplete_page("Example", 2).unwrap()), vec!["Story e"]);
+    }
+}
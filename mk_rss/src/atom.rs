@@ -0,0 +1,164 @@
+use indoc::formatdoc;
+
+use super::feed::{Feed, FeedItem};
+use super::guid::Guid;
+use super::xml;
+
+impl Feed {
+    /// The same feed as `to_rss_xml`, as an Atom 1.0 document
+    pub fn to_atom_xml(&self) -> String {
+        let entries_xml = self.items
+            .iter()
+            .map(FeedItem::to_atom_xml)
+            .collect::<Vec<String>>()
+            .join("");
+
+        let mut optional_xml = vec![];
+
+        if let Some(description) = &self.channel.description {
+            optional_xml.push(format!("<subtitle>{}</subtitle>", xml::escape(description)));
+        }
+
+        if let Some(image) = &self.channel.image {
+            optional_xml.push(format!("<icon>{}</icon>", xml::escape(image.as_str())));
+        }
+
+        if let Some(self_url) = &self.self_url {
+            optional_xml.push(format!(
+                "<link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>",
+                xml::escape(self_url.as_str())
+            ));
+        }
+
+        let language_xml = match &self.channel.language {
+            Some(language) => format!(" xml:lang=\"{}\"", xml::escape(language)),
+            None => String::new(),
+        };
+
+        // Atom requires an id that never changes, the url we were served from is the closest we have.
+        let id = self.self_url.as_ref().unwrap_or(&self.url);
+
+        formatdoc!{"
+            <feed xmlns=\"http://www.w3.org/2005/Atom\"{}>
+            <title>{}</title>
+            <id>{}</id>
+            <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>
+            {}
+            <updated>{}</updated>
+            <generator version=\"{}\">mk-rss</generator>
+
+            {}
+
+            </feed>
+            ",
+            language_xml,
+            xml::escape(&self.name),
+            xml::escape(id.as_str()),
+            xml::escape(self.url.as_str()),
+            optional_xml.join("\n"),
+            self.last_build_date.to_rfc3339(),
+            env!("CARGO_PKG_VERSION"),
+            entries_xml
+        }
+    }
+}
+
+impl FeedItem {
+    pub fn to_atom_xml(&self) -> String {
+        let mut optional_xml = vec![];
+
+        if let Some(description) = &self.description {
+            optional_xml.push(format!("<summary type=\"html\">{}</summary>", xml::escape(description)));
+        }
+
+        if let Some(author) = &self.author {
+            optional_xml.push(format!("<author><name>{}</name></author>", xml::escape(author)));
+        }
+
+        if let Some(enclosure) = &self.enclosure {
+            let mime_type = match &enclosure.mime_type {
+                Some(mime_type) => format!(" type=\"{}\"", xml::escape(mime_type)),
+                None => String::new(),
+            };
+
+            let length = match enclosure.length {
+                Some(length) => format!(" length=\"{}\"", length),
+                None => String::new(),
+            };
+
+            optional_xml.push(format!("<link rel=\"enclosure\" href=\"{}\"{}{}/>", xml::escape(enclosure.url.as_str()), mime_type, length));
+        }
+
+        optional_xml.extend(
+            self.categories
+                .iter()
+                .map(|category| format!("<category term=\"{}\"/>", xml::escape(category)))
+        );
+
+        formatdoc! {"
+            <entry>
+                <title>{}</title>
+                <link rel=\"alternate\" href=\"{}\"/>
+                <id>{}</id>
+                <published>{}</published>
+                <updated>{}</updated>
+                {}
+            </entry>
+            ",
+            xml::escape(&self.title),
+            xml::escape(self.url.as_str()),
+            xml::escape(&atom_id(&self.guid)),
+            self.pub_date.to_rfc3339(),
+            self.pub_date.to_rfc3339(),
+            optional_xml.join("\n")
+        }
+    }
+}
+
+/// Atom ids must be IRIs, so guids that aren't urls are wrapped in a `urn`
+fn atom_id(guid: &Guid) -> String {
+    if guid.is_perma_link {
+        guid.value.clone()
+    } else {
+        format!("urn:mk-rss:{}", form_urlencoded::byte_serialize(guid.value.as_bytes()).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Url;
+
+    #[test]
+    pub fn to_atom_xml_should_produce_a_valid_atom_feed() {
+        let item = FeedItem {
+            guid: Guid::opaque("story 1"),
+            description: Some("<p>Hello</p>".into()),
+            author: Some("Jane Doe".into()),
+            categories: vec!["news".into()],
+            ..FeedItem::for_test("The Story", "https://example.com/the-story")
+        };
+        let feed = Feed {
+            self_url: Some(Url::parse("https://feeds.example.com/fish-chips.atom").unwrap()),
+            ..Feed::for_test("Fish & Chips", vec![item])
+        };
+
+        let xml = feed.to_atom_xml();
+        let document = roxmltree::Document::parse(&xml).unwrap();
+        let root = document.root_element();
+        assert!(root.has_tag_name(("http://www.w3.org/2005/Atom", "feed")));
+
+        let text = |parent: roxmltree::Node, name: &str| parent
+            .children()
+            .find(|node| node.tag_name().name() == name)
+            .and_then(|node| node.text())
+            .map(str::to_string);
+
+        assert_eq!(text(root, "title"), Some("Fish & Chips".into()));
+        assert_eq!(text(root, "id"), Some("https://feeds.example.com/fish-chips.atom".into()));
+
+        let entry = root.children().find(|node| node.has_tag_name("entry")).unwrap();
+        assert_eq!(text(entry, "id"), Some("urn:mk-rss:story+1".into()));
+        assert_eq!(text(entry, "summary"), Some("<p>Hello</p>".into()));
+    }
+}
@@ -0,0 +1,104 @@
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+/// Describes a feed as a whole, rather than any of its items.
+///
+/// Anything not given in the request is read from the scraped page where possible.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ChannelMetadata {
+    pub description: Option<String>,
+
+    /// The language of the feed, e.g. `en-us`
+    pub language: Option<String>,
+
+    /// An image (or icon) representing the feed
+    pub image: Option<Url>,
+
+    /// How many minutes readers should cache the feed for
+    pub ttl: Option<u32>,
+}
+
+impl ChannelMetadata {
+    /// Read what we can about a page from its `<head>`. Relative urls are resolved against `base_url`.
+    pub fn from_document(document: &Html, base_url: &Url) -> ChannelMetadata {
+        let description = select_attr(document, r#"meta[name="description"]"#, "content")
+            .or_else(|| select_attr(document, r#"meta[property="og:description"]"#, "content"))
+            .or_else(|| page_title(document));
+
+        let image = select_attr(document, r#"meta[property="og:image"]"#, "content")
+            .or_else(|| select_attr(document, r#"link[rel~="apple-touch-icon"]"#, "href"))
+            .or_else(|| select_attr(document, r#"link[rel~="icon"]"#, "href"))
+            .and_then(|image| base_url.join(&image).ok());
+
+        ChannelMetadata {
+            description,
+            language: select_attr(document, "html[lang]", "lang"),
+            image,
+            ttl: None,
+        }
+    }
+
+    /// Fill in anything missing from `self` with `other`
+    pub fn or(self, other: ChannelMetadata) -> ChannelMetadata {
+        ChannelMetadata {
+            description: self.description.or(other.description),
+            language: self.language.or(other.language),
+            image: self.image.or(other.image),
+            ttl: self.ttl.or(other.ttl),
+        }
+    }
+}
+
+/// The `og:title` of a page, falling back to its `<title>`
+pub fn page_title(document: &Html) -> Option<String> {
+    let og_title = Selector::parse(r#"meta[property="og:title"]"#).expect("selector should be valid");
+    let title = Selector::parse("title").expect("selector should be valid");
+
+    document
+        .select(&og_title)
+        .next()
+        .and_then(|meta| meta.value().attr("content"))
+        .map(str::to_string)
+        .or_else(|| document.select(&title).next().map(|title| title.text().collect()))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+/// The trimmed value of `attr` on the first node matching `selector`, if it isn't empty
+fn select_attr(document: &Html, selector: &str, attr: &str) -> Option<String> {
+    let selector = Selector::parse(selector).expect("selector should be valid");
+
+    document
+        .select(&selector)
+        .find_map(|element| element.value().attr(attr))
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn from_document_should_read_the_page_head() {
+        let document = Html::parse_document(indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-AU">
+            <head>
+              <title>Example Blog</title>
+              <link rel="shortcut icon" href="/favicon.png">
+            </head>
+            </html>
+        "#});
+
+        let base_url = Url::parse("https://example.com/blog/").unwrap();
+
+        assert_eq!(ChannelMetadata::from_document(&document, &base_url), ChannelMetadata {
+            description: Some("Example Blog".into()),
+            language: Some("en-AU".into()),
+            image: Some(Url::parse("https://example.com/favicon.png").unwrap()),
+            ttl: None,
+        });
+    }
+}
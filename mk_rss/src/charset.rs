@@ -0,0 +1,97 @@
+use encoding_rs::{Encoding, UTF_8};
+use once_cell::sync::Lazy;
+use regex::bytes::Regex;
+
+/// How far into a document we look for a `<meta charset>` or XML declaration, the same as browsers do
+const PRESCAN_LENGTH: usize = 1024;
+
+static META_CHARSET: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)<meta[^>]*charset\s*=\s*["']?\s*([a-z0-9_:.\-]+)"#).expect("regex should be valid")
+});
+
+static XML_DECLARATION_ENCODING: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)^<\?xml[^>]*encoding\s*=\s*["']([a-z0-9_:.\-]+)["']"#).expect("regex should be valid")
+});
+
+/// Decode `body` as text, using the first encoding we find out about from:
+///
+/// 1. `encoding_override`, for sites that get it wrong
+/// 2. A byte order mark
+/// 3. The charset of the `content_type` header
+/// 4. A `<meta charset>` (or `http-equiv`) tag or an XML declaration near the start of the document
+/// 5. UTF-8
+///
+/// Anything that can't be decoded is replaced rather than failing the whole document.
+pub fn decode(body: &[u8], content_type: Option<&str>, encoding_override: Option<&'static Encoding>) -> String {
+    let encoding = encoding_override
+        .or_else(|| Encoding::for_bom(body).map(|(encoding, _)| encoding))
+        .or_else(|| content_type.and_then(content_type_charset))
+        .or_else(|| declared_charset(body))
+        .unwrap_or(UTF_8);
+
+    let (text, _) = encoding.decode_with_bom_removal(body);
+    text.into_owned()
+}
+
+/// The encoding named by the `charset` parameter of a Content-Type header, e.g. `text/html; charset=Shift_JIS`
+fn content_type_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| {
+            let (name, value) = parameter.split_at(parameter.find('=')?);
+            Some((name.trim(), value[1..].trim().trim_matches(|c| c == '"' || c == '\'')))
+        })
+        .find(|(name, _)| name.eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.as_bytes()))
+}
+
+/// The encoding the document declares itself, in a meta tag or XML declaration
+fn declared_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let prescan = &body[..body.len().min(PRESCAN_LENGTH)];
+    let label = XML_DECLARATION_ENCODING
+        .captures(prescan)
+        .or_else(|| META_CHARSET.captures(prescan))
+        .and_then(|captures| captures.get(1))?;
+
+    let encoding = Encoding::for_label(label.as_bytes())?;
+
+    // Text that claims to be UTF-16 but that we could read ASCII tags from can't really be UTF-16
+    if encoding == encoding_rs::UTF_16LE || encoding == encoding_rs::UTF_16BE {
+        Some(UTF_8)
+    } else {
+        Some(encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn decode_should_use_the_declared_charset() {
+        let (title, _, _) = encoding_rs::SHIFT_JIS.encode("ニュース");
+        let page = [&b"<html><head><meta charset=\"shift_jis\"><title>"[..], &title, b"</title>"].concat();
+        assert!(decode(&page, Some("text/html"), None).contains("<title>ニュース</title>"));
+
+        let (title, _, _) = encoding_rs::WINDOWS_1251.encode("Новости");
+        let page = [&b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=windows-1251\"><title>"[..], &title, b"</title>"].concat();
+        assert!(decode(&page, None, None).contains("<title>Новости</title>"));
+
+        let feed = [&b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><rss><title>"[..], &title, b"</title></rss>"].concat();
+        assert!(decode(&feed, Some("application/rss+xml"), None).contains("<title>Новости</title>"));
+    }
+
+    #[test]
+    pub fn decode_should_prefer_the_override_bom_then_header() {
+        let (title, _, _) = encoding_rs::WINDOWS_1251.encode("Новости");
+        let page = [&b"<meta charset=\"utf-8\"><title>"[..], &title, b"</title>"].concat();
+
+        assert!(decode(&page, Some("text/html; charset=\"windows-1251\""), None).contains("Новости"));
+        assert!(decode(&page, Some("text/html; charset=utf-8"), encoding_rs::Encoding::for_label(b"cp1251")).contains("Новости"));
+        assert!(!decode(&page, None, None).contains("Новости"));
+
+        let page = [&b"\xEF\xBB\xBF"[..], "Новости".as_bytes()].concat();
+        assert_eq!(decode(&page, Some("text/html; charset=windows-1251"), None), "Новости");
+    }
+}
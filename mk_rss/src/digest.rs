@@ -0,0 +1,302 @@
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::feed::{Feed, FeedItem};
+use super::xml;
+
+/// How to secure the connection to an SMTP server
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SmtpTls {
+    /// A plain connection, only suitable for a server on the local machine
+    None,
+
+    /// Upgrade a plain connection with `STARTTLS`, usually on port 587
+    StartTls,
+
+    /// Connect with TLS from the start, usually on port 465
+    Tls,
+}
+
+/// Where and how to send email
+#[derive(Debug, PartialEq, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+
+    /// Defaults to the usual port for `tls`
+    pub port: Option<u16>,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// The new items of one feed
+#[derive(Debug)]
+pub struct DigestSection<'a> {
+    pub feed: &'a Feed,
+    pub items: Vec<&'a FeedItem>,
+}
+
+/// An email listing the new items across several feeds
+#[derive(Debug, Default)]
+pub struct Digest<'a> {
+    pub sections: Vec<DigestSection<'a>>,
+}
+
+impl<'a> Digest<'a> {
+    /// Add the `items` that are new in `feed`. Feeds without new items are left out.
+    pub fn add(&mut self, feed: &'a Feed, items: Vec<&'a FeedItem>) {
+        if !items.is_empty() {
+            self.sections.push(DigestSection { feed, items });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    fn item_count(&self) -> usize {
+        self.sections.iter().map(|section| section.items.len()).sum()
+    }
+
+    pub fn subject(&self) -> String {
+        let feed_names: Vec<&str> = self.sections.iter().map(|section| section.feed.name.as_str()).collect();
+
+        match self.item_count() {
+            1 => format!("1 new item from {}", feed_names.join(", ")),
+            count => format!("{} new items from {}", count, feed_names.join(", ")),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        self.sections
+            .iter()
+            .map(|section| {
+                let items: Vec<String> = section.items
+                    .iter()
+                    .map(|item| format!("- {}\n  {}\n  {}", item.title, item.url, item_byline(item)))
+                    .collect();
+
+                format!("{}\n{}\n\n{}\n", section.feed.name, "=".repeat(section.feed.name.chars().count()), items.join("\n\n"))
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    pub fn to_html(&self) -> String {
+        let sections: Vec<String> = self.sections
+            .iter()
+            .map(|section| {
+                let items: Vec<String> = section.items
+                    .iter()
+                    .map(|item| format!(
+                        "<li><a href=\"{}\">{}</a><br><small>{}</small></li>",
+                        xml::escape(item.url.as_str()),
+                        xml::escape(&item.title),
+                        xml::escape(&item_byline(item))
+                    ))
+                    .collect();
+
+                format!(
+                    "<h2><a href=\"{}\">{}</a></h2>\n<ul>\n{}\n</ul>",
+                    xml::escape(section.feed.url.as_str()),
+                    xml::escape(&section.feed.name),
+                    items.join("\n")
+                )
+            })
+            .collect();
+
+        format!("<!DOCTYPE html>\n<html>\n<body>\n{}\n</body>\n</html>\n", sections.join("\n"))
+    }
+
+    /// Email this digest to every address in `to`
+    pub async fn send(&self, config: &SmtpConfig, from: &str, to: &[String]) -> anyhow::Result<()> {
+        let from: Mailbox = from.parse().map_err(|e| anyhow::anyhow!("Could not parse from address {}: {}", from, e))?;
+        let mut message = Message::builder().from(from).subject(self.subject());
+
+        for address in to {
+            let address: Mailbox = address.parse().map_err(|e| anyhow::anyhow!("Could not parse to address {}: {}", address, e))?;
+            message = message.to(address);
+        }
+
+        let message = message.multipart(MultiPart::alternative_plain_html(self.to_text(), self.to_html()))?;
+
+        let transport = match config.tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(config.host.as_str()),
+            SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        };
+
+        let transport = match config.port {
+            Some(port) => transport.port(port),
+            None => transport,
+        };
+
+        let transport = match (&config.username, &config.password) {
+            (Some(username), Some(password)) => transport.credentials(Credentials::new(username.clone(), password.clone())),
+            (None, None) => transport,
+            (Some(_), None) => return Err(anyhow::anyhow!("An SMTP username needs a password too")),
+            (None, Some(_)) => return Err(anyhow::anyhow!("An SMTP password needs a username too")),
+        };
+
+        transport
+            .build()
+            .send(message)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not send digest through {}: {}", config.host, e))?;
+
+        Ok(())
+    }
+}
+
+/// When the item was published, and by whom if we know
+fn item_byline(item: &FeedItem) -> String {
+    let date = item.pub_date.format("%e %B %Y").to_string();
+
+    match &item.author {
+        Some(author) => format!("{} by {}", date.trim(), author),
+        None => date.trim().to_string(),
+    }
+}
+
+impl TryFrom<&str> for SmtpTls {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "none" => Ok(SmtpTls::None),
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Tls),
+            _ => Err(anyhow::anyhow!("{} is not a valid SMTP TLS mode (valid modes are 'none', 'starttls' and 'tls')", value))
+        }
+    }
+}
+
+impl FromStr for SmtpTls {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+impl fmt::Display for SmtpTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmtpTls::None => write!(f, "none"),
+            SmtpTls::StartTls => write!(f, "starttls"),
+            SmtpTls::Tls => write!(f, "tls"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn feed() -> Feed {
+        let item = FeedItem { author: Some("Jane Doe".into()), ..FeedItem::for_test("Fish & Chips", "https://example.com/the-story") };
+        Feed::for_test("Example", vec![item])
+    }
+
+    /// An SMTP server that accepts a single message and records everything it was sent
+    async fn serve_smtp_sink() -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let transcript = Arc::new(Mutex::new(String::new()));
+
+        let received = transcript.clone();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ESMTP sink\r\n").await.unwrap();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                received.lock().unwrap().push_str(&format!("{}\n", line));
+
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+
+                writer.write_all(reply).await.unwrap();
+            }
+        });
+
+        (port, transcript)
+    }
+
+    #[test]
+    pub fn digest_should_render_text_and_html() {
+        let feed = feed();
+        let mut digest = Digest::default();
+        digest.add(&feed, feed.items.iter().collect());
+        digest.add(&feed, vec![]);
+
+        assert_eq!(digest.subject(), "1 new item from Example");
+        assert_eq!(digest.to_text(), "Example\n=======\n\n- Fish & Chips\n  https://example.com/the-story\n  10 January 2021 by Jane Doe\n");
+        assert!(digest.to_html().contains("<a href=\"https://example.com/the-story\">Fish &amp; Chips</a>"));
+    }
+
+    #[tokio::test]
+    pub async fn send_should_deliver_to_an_smtp_server() {
+        let (port, transcript) = serve_smtp_sink().await;
+        let feed = feed();
+        let mut digest = Digest::default();
+        digest.add(&feed, feed.items.iter().collect());
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".into(),
+            port: Some(port),
+            tls: SmtpTls::None,
+            username: None,
+            password: None,
+        };
+
+        digest.send(&config, "mk-rss <mk-rss@example.com>", &["team@example.com".to_string()]).await.unwrap();
+
+        let transcript = transcript.lock().unwrap();
+        assert!(transcript.contains("RCPT TO:<team@example.com>"));
+        assert!(transcript.contains("Subject: 1 new item from Example"));
+        assert!(transcript.contains("https://example.com/the-story"));
+    }
+
+    #[tokio::test]
+    pub async fn send_should_refuse_half_the_credentials() {
+        let feed = feed();
+        let mut digest = Digest::default();
+        digest.add(&feed, feed.items.iter().collect());
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".into(),
+            port: Some(1),
+            tls: SmtpTls::None,
+            username: Some("mk-rss".into()),
+            password: None,
+        };
+
+        let error = digest.send(&config, "mk-rss@example.com", &["team@example.com".to_string()]).await.unwrap_err();
+        assert_eq!(error.to_string(), "An SMTP username needs a password too");
+    }
+}
@@ -0,0 +1,180 @@
+use futures::future;
+use reqwest::Url;
+use scraper::{Html, Selector};
+
+use super::fetch::fetch_url;
+use super::rate_limit::HostLimits;
+use super::upstream_feed;
+
+/// Paths where sites commonly serve a feed without advertising it
+const COMMON_FEED_PATHS: &[&str] = &["/feed", "/rss.xml", "/atom.xml", "/feed.xml", "/index.xml"];
+
+/// The `type`s of `<link rel="alternate">` that point at a feed
+const FEED_TYPES: &[&str] = &["application/rss+xml", "application/atom+xml", "application/rdf+xml"];
+
+/// A feed a site already provides
+#[derive(Debug, PartialEq, Eq)]
+pub struct DiscoveredFeed {
+    pub url: Url,
+
+    /// The title the page gives the feed, if it's advertised
+    pub title: Option<String>,
+
+    /// Whether the page advertises this feed with a `<link rel="alternate">`, rather than us guessing its path
+    pub advertised: bool,
+
+    /// How many items the feed currently has, or `None` if it couldn't be fetched or parsed
+    pub item_count: Option<usize>,
+}
+
+/// Find the feeds the page at `url` already provides.
+///
+/// Advertised feeds are always reported, feeds at common paths are only reported if they parse.
+pub async fn discover_feeds(url: Url) -> anyhow::Result<Vec<DiscoveredFeed>> {
+    let body = fetch_url(url.clone(), &HostLimits::default()).await?.text(None);
+    let candidates = candidate_feeds(&Html::parse_document(&body), &url);
+
+    let item_counts = future::join_all(candidates.iter().map(|candidate| count_items(candidate.url.clone()))).await;
+
+    Ok(counted_feeds(candidates, item_counts))
+}
+
+/// The feeds `document` advertises, followed by the common feed paths on `url`'s site it doesn't
+fn candidate_feeds(document: &Html, url: &Url) -> Vec<DiscoveredFeed> {
+    let mut candidates = advertised_feeds(document, url);
+
+    for path in COMMON_FEED_PATHS {
+        if let Ok(feed_url) = url.join(path) {
+            if !candidates.iter().any(|candidate| candidate.url == feed_url) {
+                candidates.push(DiscoveredFeed { url: feed_url, title: None, advertised: false, item_count: None });
+            }
+        }
+    }
+
+    candidates
+}
+
+/// `candidates` with their `item_counts`, leaving out the guessed feeds we couldn't read
+fn counted_feeds(candidates: Vec<DiscoveredFeed>, item_counts: Vec<Option<usize>>) -> Vec<DiscoveredFeed> {
+    candidates
+        .into_iter()
+        .zip(item_counts)
+        .map(|(candidate, item_count)| DiscoveredFeed { item_count, ..candidate })
+        .filter(|candidate| candidate.advertised || candidate.item_count.is_some())
+        .collect()
+}
+
+/// The feeds `document` advertises in its `<link rel="alternate">` tags. Relative urls are resolved against `base_url`.
+pub fn advertised_feeds(document: &Html, base_url: &Url) -> Vec<DiscoveredFeed> {
+    let selector = Selector::parse(r#"link[rel~="alternate"][href]"#).expect("selector should be valid");
+
+    let mut feeds: Vec<DiscoveredFeed> = vec![];
+    for link in document.select(&selector) {
+        let link = link.value();
+        let is_feed = link
+            .attr("type")
+            .map(|link_type| FEED_TYPES.contains(&link_type.trim().to_lowercase().as_str()))
+            .unwrap_or(false);
+
+        let url = match link.attr("href").map(|href| base_url.join(href.trim())) {
+            Some(Ok(url)) if is_feed => url,
+            _ => continue,
+        };
+
+        if !feeds.iter().any(|feed| feed.url == url) {
+            feeds.push(DiscoveredFeed {
+                url,
+                title: link.attr("title").map(|title| title.trim().to_string()).filter(|title| !title.is_empty()),
+                advertised: true,
+                item_count: None,
+            });
+        }
+    }
+
+    feeds
+}
+
+async fn count_items(url: Url) -> Option<usize> {
+    check_feed(url).await.ok()
+}
+
+/// How many items the RSS or Atom feed at `url` has, or why it couldn't be read
+pub async fn check_feed(url: Url) -> anyhow::Result<usize> {
+    let body = fetch_url(url, &HostLimits::default()).await?.text(None);
+    Ok(upstream_feed::parse_items(&body)?.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn advertised_feeds_should_only_include_feed_links() {
+        let document = Html::parse_document(indoc! {r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+              <link rel="alternate" type="application/rss+xml" title="Example Blog" href="/blog/feed.xml">
+              <link rel="alternate" type="application/atom+xml" href="https://example.com/atom.xml">
+              <link rel="alternate" hreflang="de" href="/de/">
+              <link rel="stylesheet" type="text/css" href="/style.css">
+            </head>
+            </html>
+        "#});
+
+        let base_url = Url::parse("https://example.com/blog/").unwrap();
+
+        assert_eq!(advertised_feeds(&document, &base_url), vec![
+            DiscoveredFeed {
+                url: Url::parse("https://example.com/blog/feed.xml").unwrap(),
+                title: Some("Example Blog".into()),
+                advertised: true,
+                item_count: None,
+            },
+            DiscoveredFeed {
+                url: Url::parse("https://example.com/atom.xml").unwrap(),
+                title: None,
+                advertised: true,
+                item_count: None,
+            },
+        ]);
+    }
+
+    #[test]
+    pub fn discovered_feeds_should_include_common_paths_that_parse() {
+        let document = Html::parse_document(indoc! {r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+              <link rel="alternate" type="application/rss+xml" href="/feed">
+              <link rel="alternate" type="application/atom+xml" href="/blog/atom.xml">
+            </head>
+            </html>
+        "#});
+
+        let url = Url::parse("https://example.com/blog/").unwrap();
+        let candidates = candidate_feeds(&document, &url);
+
+        let urls: Vec<&str> = candidates.iter().map(|candidate| candidate.url.as_str()).collect();
+        assert_eq!(urls, vec![
+            "https://example.com/feed",
+            "https://example.com/blog/atom.xml",
+            "https://example.com/rss.xml",
+            "https://example.com/atom.xml",
+            "https://example.com/feed.xml",
+            "https://example.com/index.xml",
+        ]);
+
+        let discovered = counted_feeds(candidates, vec![Some(3), None, None, Some(5), None, None]);
+        let found: Vec<(&str, bool, Option<usize>)> = discovered
+            .iter()
+            .map(|feed| (feed.url.as_str(), feed.advertised, feed.item_count))
+            .collect();
+        assert_eq!(found, vec![
+            ("https://example.com/feed", true, Some(3)),
+            ("https://example.com/blog/atom.xml", true, None),
+            ("https://example.com/atom.xml", false, Some(5)),
+        ]);
+    }
+}
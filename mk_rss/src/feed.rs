@@ -1,21 +1,51 @@
 use indoc::formatdoc;
 use reqwest::Url;
 use chrono::{DateTime, Duration, Local};
+use std::cmp::Reverse;
+use std::collections::HashSet;
 
-use super::website::{Website, WebsiteElement};
+use super::aggregate::{ItemSource, SourceTag};
+use super::channel::ChannelMetadata;
+use super::website::{self, Website, WebsiteElement};
+use super::guid::Guid;
+use super::media::{self, Enclosure};
+use super::paging::{self, ArchiveLinks};
+use super::xml;
+
+/// How many minutes readers should cache a feed for unless the request says otherwise.
+///
+/// This matches how long `fetch` caches each page, refreshing more often won't find anything new.
+const DEFAULT_TTL: u32 = 30;
 
 #[derive(Debug)]
 pub struct Feed {
     pub name: String,
     pub url: Url,
-    pub items: Vec<FeedItem>
+    pub items: Vec<FeedItem>,
+    pub channel: ChannelMetadata,
+    pub last_build_date: DateTime<Local>,
+
+    /// The url this feed is served from, if known
+    pub self_url: Option<Url>,
+
+    /// Where readers can find the items that are no longer in this feed
+    pub archive_links: ArchiveLinks
 }
 
 #[derive(Debug)]
 pub struct FeedItem {
     pub title: String,
     pub url: Url,
-    pub pub_date: DateTime<Local>
+    pub pub_date: DateTime<Local>,
+    pub description: Option<String>,
+    pub guid: Guid,
+    pub image: Option<Url>,
+    pub enclosure: Option<Enclosure>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+
+    /// The feed this item came from, when it's part of an aggregated feed
+    pub source: Option<ItemSource>
 }
 
 impl Feed {
@@ -26,7 +56,52 @@ impl Feed {
         Feed {
             name: website.name,
             url: website.url,
-            items
+            items,
+            channel: website.channel,
+            last_build_date: now,
+            self_url: None,
+            archive_links: ArchiveLinks::default()
+        }
+    }
+
+    /// Merge `feeds` into a single feed, newest items first.
+    ///
+    /// Each item is tagged with the feed it came from, and items that appear in more than one
+    /// feed are only kept once.
+    pub fn merge(name: String, url: Url, feeds: Vec<Feed>, source_tag: SourceTag, max_items: usize) -> Feed {
+        let last_build_date = feeds
+            .iter()
+            .map(|feed| feed.last_build_date)
+            .max()
+            .unwrap_or_else(Local::now);
+
+        let mut items: Vec<FeedItem> = feeds
+            .into_iter()
+            .flat_map(|feed| {
+                let source = ItemSource { name: feed.name, url: feed.url, tag: source_tag };
+                feed.items
+                    .into_iter()
+                    .map(move |item| FeedItem { source: Some(source.clone()), ..item })
+            })
+            .collect();
+
+        items.sort_by_key(|item| Reverse(item.pub_date));
+
+        let mut seen_urls = HashSet::new();
+        let items: Vec<FeedItem> = items
+            .into_iter()
+            .filter(|item| seen_urls.insert(website::normalize_url(&item.url)))
+            .take(max_items)
+            .collect();
+
+        Feed {
+            name,
+            url,
+            items: Feed::match_pub_dates_to_order(items),
+            channel: ChannelMetadata::default(),
+            last_build_date,
+            self_url: None,
+            archive_links: ArchiveLinks::default()
         }
     }
 
@@ -37,30 +112,73 @@ impl Feed {
             .collect::<Vec<String>>()
             .join("");
 
+        let description_xml = match &self.channel.description {
+            Some(description) => format!("<description>{}</description>", xml::escape(description)),
+            None => "<description/>".to_string(),
+        };
+
+        let mut optional_xml = vec![];
+
+        if let Some(language) = &self.channel.language {
+            optional_xml.push(format!("<language>{}</language>", xml::escape(language)));
+        }
+
+        if let Some(image) = &self.channel.image {
+            optional_xml.push(formatdoc! {"
+                <image>
+                    <url>{}</url>
+                    <title>{}</title>
+                    <link>{}</link>
+                </image>",
+                xml::escape(image.as_str()),
+                xml::escape(&self.name),
+                xml::escape(self.url.as_str())
+            });
+        }
+
+        if let Some(self_url) = &self.self_url {
+            optional_xml.push(format!(
+                "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>",
+                xml::escape(self_url.as_str())
+            ));
+        }
+
+        if self.archive_links != ArchiveLinks::default() {
+            optional_xml.push(self.archive_links.to_rss_xml());
+        }
+
         formatdoc!{"
-            <rss version=\"2.0\">
+            <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:media=\"http://search.yahoo.com/mrss/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:fh=\"{}\">
             <channel>
 
             <title>{}</title>
             <link>{}</link>
-            <guid>{}</guid>
-            <description/>
+            {}
+            {}
+            <lastBuildDate>{}</lastBuildDate>
+            <ttl>{}</ttl>
+            <generator>mk-rss {}</generator>
 
             {}
 
             </channel>
             </rss>
             ",
-            self.name,
-            self.url,
-            self.url,
+            paging::HISTORY_NAMESPACE,
+            xml::escape(&self.name),
+            xml::escape(self.url.as_str()),
+            description_xml,
+            optional_xml.join("\n"),
+            self.last_build_date.to_rfc2822(),
+            self.channel.ttl.unwrap_or(DEFAULT_TTL),
+            env!("CARGO_PKG_VERSION"),
             items_xml
         }
     }
 
     fn infer_feed_items(elements: Vec<WebsiteElement>, now: DateTime<Local>) -> Vec<FeedItem> {
         let mut previous_pub_date = elements
-            .get(0)
+            .first()
             .and_then(|element| element.pub_date)
             .unwrap_or(now);
 
@@ -73,7 +191,14 @@ impl Feed {
                 FeedItem {
                     title: element.title,
                     url: element.url,
-                    pub_date
+                    pub_date,
+                    description: element.description,
+                    guid: element.guid,
+                    image: element.image,
+                    enclosure: element.enclosure,
+                    author: element.author,
+                    categories: element.categories,
+                    source: None
                 }
             })
             .collect()
@@ -86,7 +211,7 @@ impl Feed {
     /// undefined. To solve this we jitter the `pub_date` slightly to make the order
     /// match.
     fn match_pub_dates_to_order(items: Vec<FeedItem>) -> Vec<FeedItem> {
-        let mut previous_item_pub_date = match items.get(0) {
+        let mut previous_item_pub_date = match items.first() {
             Some(first_item) => first_item.pub_date,
             None => return vec![]
         };
@@ -110,19 +235,101 @@ impl Feed {
 
 impl FeedItem {
     pub fn to_rss_xml(&self) -> String {
+        let description_xml = match &self.description {
+            Some(description) => format!("<description>{}</description>", xml::escape(description)),
+            None => "<description/>".to_string(),
+        };
+
+        let media_xml = self.image
+            .iter()
+            .map(media::thumbnail_xml)
+            .chain(self.enclosure.iter().map(Enclosure::to_rss_xml))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut author_xml = vec![];
+        if let Some(author) = &self.author {
+            author_xml.push(format!("<dc:creator>{}</dc:creator>", xml::escape(author)));
+
+            // RSS expects `<author>` to be an email address, so we only emit it when we have one
+            if author.contains('@') {
+                author_xml.push(format!("<author>{}</author>", xml::escape(author)));
+            }
+        }
+
+        let category_xml = self.categories
+            .iter()
+            .map(|category| format!("<category>{}</category>", xml::escape(category)))
+            .collect::<Vec<String>>();
+
+        let source_xml = self.source
+            .as_ref()
+            .map(ItemSource::to_rss_xml)
+            .unwrap_or_default();
+
         formatdoc! {"
             <item>
                 <title>{}</title>
                 <link>{}</link>
-                <guid>{}</guid>
+                {}
                 <pubDate>{}</pubDate>
-                <description/>
+                {}
+                {}
+                {}
+                {}
+                {}
             </item>
             ",
-            self.title,
-            self.url,
-            self.url,
-            self.pub_date.to_rfc2822()
+            xml::escape(&self.title),
+            xml::escape(self.url.as_str()),
+            self.guid.to_rss_xml(),
+            self.pub_date.to_rfc2822(),
+            description_xml,
+            media_xml,
+            author_xml.join("\n"),
+            category_xml.join("\n"),
+            source_xml
+        }
+    }
+}
+
+/// Fixtures for tests here and in crates that enable the `test-support` feature
+#[cfg(any(test, feature = "test-support"))]
+impl Feed {
+    /// A feed of `items` at https://example.com/, last built at noon on 10 January 2021
+    pub fn for_test(name: &str, items: Vec<FeedItem>) -> Feed {
+        use chrono::TimeZone;
+
+        Feed {
+            name: name.into(),
+            url: Url::parse("https://example.com/").unwrap(),
+            items,
+            channel: ChannelMetadata::default(),
+            last_build_date: Local.ymd(2021, 1, 10).and_hms(12, 0, 0),
+            self_url: None,
+            archive_links: ArchiveLinks::default()
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl FeedItem {
+    /// An item linking to `url` with a permalink guid, published at noon on 10 January 2021
+    pub fn for_test(title: &str, url: &str) -> FeedItem {
+        use chrono::TimeZone;
+
+        let url = Url::parse(url).unwrap();
+        FeedItem {
+            title: title.into(),
+            guid: Guid::permalink(&url),
+            url,
+            pub_date: Local.ymd(2021, 1, 10).and_hms(12, 0, 0),
+            description: None,
+            image: None,
+            enclosure: None,
+            author: None,
+            categories: vec![],
+            source: None
         }
     }
 }
@@ -137,10 +344,12 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::zero_prefixed_literal, clippy::get_first)]
     pub fn from_website_should_infer_missing_dates_from_previous_date() {
         let website = Website {
             name: "Test Website".into(),
             url: Url::parse("https://example.com/feed/").unwrap(),
+            channel: ChannelMetadata::default(),
             elements: vec![
                 website_element("The Story A", None),
                 website_element("The Story B", None),
@@ -169,10 +378,12 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::zero_prefixed_literal, clippy::get_first)]
     pub fn from_website_should_reorder_pub_date_by_input_order() {
         let website = Website {
             name: "Test Website".into(),
             url: Url::parse("https://example.com/feed/").unwrap(),
+            channel: ChannelMetadata::default(),
             elements: vec![
                 website_element("The Story A", Some(Local.ymd(2020, 03, 01).and_hms(13, 0, 0))),
                 website_element("The Story B", Some(Local.ymd(2020, 03, 02).and_hms(13, 0, 0))),
@@ -197,11 +408,94 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn merge_should_interleave_by_date_and_dedupe_by_url() {
+        let item = |title: &str, url: &str, day: u32| FeedItem {
+            pub_date: Local.ymd(2021, 1, day).and_hms(12, 0, 0),
+            ..FeedItem::for_test(title, url)
+        };
+
+        let feed = |name: &str, items: Vec<FeedItem>| Feed {
+            url: Url::parse("https://example.com/").unwrap().join(name).unwrap(),
+            ..Feed::for_test(name, items)
+        };
+
+        let team = || vec![
+            feed("alice", vec![
+                item("Alice 2", "https://example.com/shared", 3),
+                item("Alice 1", "https://example.com/alice-1", 1),
+            ]),
+            feed("bob", vec![
+                item("Bob 1", "https://example.com/bob-1", 2),
+                item("Bob shared", "https://example.com/shared/", 1),
+            ]),
+        ];
+
+        let merged = Feed::merge("Team".into(), element_url(), team(), SourceTag::Source, 30);
+
+        let titles: Vec<&str> = merged.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, vec!["Alice 2", "Bob 1", "Alice 1"]);
+        assert!(merged.items[2].pub_date < Local.ymd(2021, 1, 1).and_hms(12, 0, 0) + Duration::seconds(1));
+
+        let source = merged.items[1].source.as_ref().unwrap();
+        assert_eq!(source.to_rss_xml(), "<source url=\"https://example.com/bob\">bob</source>");
+
+        let limited = Feed::merge("Team".into(), element_url(), team(), SourceTag::Category, 2);
+
+        let titles: Vec<&str> = limited.items.iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(titles, vec!["Alice 2", "Bob 1"]);
+
+        let source = limited.items[1].source.as_ref().unwrap();
+        assert_eq!(source.to_rss_xml(), "<category domain=\"https://example.com/bob\">bob</category>");
+    }
+
+    #[test]
+    pub fn to_rss_xml_should_describe_the_channel() {
+        let website = Website {
+            name: "Test Website".into(),
+            url: Url::parse("https://example.com/feed/").unwrap(),
+            channel: ChannelMetadata {
+                description: Some("News & updates".into()),
+                language: Some("en-US".into()),
+                image: None,
+                ttl: Some(60),
+            },
+            elements: vec![],
+        };
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let mut feed = Feed::from_website(website, now);
+        feed.self_url = Some(Url::parse("https://mk-rss.example.com/?name=Test").unwrap());
+
+        let xml = feed.to_rss_xml();
+        let document = roxmltree::Document::parse(&xml).unwrap();
+        let channel = document.descendants().find(|node| node.has_tag_name("channel")).unwrap();
+        let child_text = |name: &str| channel
+            .children()
+            .find(|node| node.tag_name().name() == name)
+            .and_then(|node| node.text());
+
+        assert_eq!(child_text("description"), Some("News & updates"));
+        assert_eq!(child_text("language"), Some("en-US"));
+        assert_eq!(child_text("ttl"), Some("60"));
+        assert_eq!(child_text("lastBuildDate"), Some(now.to_rfc2822().as_str()));
+        assert_eq!(child_text("guid"), None);
+
+        let self_link = channel.children().find(|node| node.has_tag_name(("http://www.w3.org/2005/Atom", "link"))).unwrap();
+        assert_eq!(self_link.attribute("href"), Some("https://mk-rss.example.com/?name=Test"));
+    }
+
     fn website_element(title: &str, pub_date: Option<DateTime<Local>>) -> WebsiteElement {
         WebsiteElement {
             title: title.into(),
             url: element_url(),
-            pub_date
+            pub_date,
+            description: None,
+            guid: Guid::permalink(&element_url()),
+            image: None,
+            enclosure: None,
+            author: None,
+            categories: vec![]
         }
     }
 }
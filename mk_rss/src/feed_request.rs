@@ -1,9 +1,22 @@
-use anyhow;
+use anyhow::Context;
+use encoding_rs::Encoding;
 use reqwest::Url;
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::cmp;
+use std::fmt;
 use std::str::FromStr;
-use scraper::Selector;
+use std::time::Duration;
+use serde_json_path::JsonPath;
+
+use super::channel::ChannelMetadata;
+use super::rewrite::{RewriteRules, TitleRewrite, HostRewrite};
+use super::guid::GuidStrategy;
+use super::html_selector::HtmlSelector;
+use super::rate_limit::HostLimits;
+use super::sitemap::{PathPattern, SitemapOptions};
+use super::token;
+use regex::Regex;
 
 #[derive(Debug, PartialEq)]
 pub struct FeedRequest {
@@ -13,46 +26,180 @@ pub struct FeedRequest {
     /// The url of this website to scrape.
     pub url: Url,
 
-    /// A css selector indicating what part of this sites HTML contains the list
-    /// of items to turn into a feed
+    /// Where to find each item (and its parts) in the scraped document
+    pub source: FeedSource,
+
+    /// The order of elements the feed should return.
+    ///
+    /// `Normal` means the same order as the webpage (top-most item will be considered the "most recent")
+    /// `Reversed` is the reverse of `Normal` (bottom-most item will be considered "most recent")
+    pub order: FeedOrder,
+
+    /// The maximum number of items to return
+    pub max_items: usize,
+
+    /// Rules used to clean up each items title and url after scraping
+    pub rewrite: RewriteRules,
+
+    /// How to generate the `<guid>` of each item
+    pub guid_strategy: GuidStrategy,
+
+    /// Channel metadata that overrides whatever we find on the page
+    pub channel: ChannelMetadata,
+
+    /// How politely we treat the site while fetching this feed
+    pub host_limits: HostLimits,
+
+    /// The encoding of the page, for sites that declare the wrong one (or none at all)
+    pub encoding: Option<&'static Encoding>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FeedSource {
+    Html(HtmlSelectors),
+    Json(JsonPaths),
+
+    /// Items come from the schema.org JSON-LD and microdata embedded in the page, so no selectors are needed
+    StructuredData,
+
+    /// Items are the pages listed in a sitemap (or sitemap index)
+    Sitemap(SitemapOptions),
+
+    /// Items come from an existing RSS or Atom feed, which we clean up and re-serialize
+    Feed,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HtmlSelectors {
+    /// A css selector (or XPath expression) indicating what part of this sites HTML contains
+    /// the list of items to turn into a feed
     ///
     /// See https://jsoup.org/apidocs/org/jsoup/select/Selector.html for pattern details
-    pub item_selector: Selector,
+    pub item_selector: HtmlSelector,
 
     /// A css selector indicating which HTML node contains each items title.
     ///
     /// We assume the text of this node contains the title
-    pub title_selector: Option<Selector>,
+    pub title_selector: Option<HtmlSelector>,
 
     /// A css selector indicating which HTML node contains each items link.
     ///
     /// This must point to an `<a>` tag and we assume the `href` is the target URL
-    pub link_selector: Option<Selector>,
+    pub link_selector: Option<HtmlSelector>,
 
     /// A css selector indicating which HTML node identifies the date this item was published
-    pub pub_date_selector: Option<Selector>,
+    pub pub_date_selector: Option<HtmlSelector>,
 
-    /// The order of elements the feed should return.
+    /// A css selector indicating which HTML node contains each items description.
     ///
-    /// `Normal` means the same order as the webpage (top-most item will be considered the "most recent")
-    /// `Reversed` is the reverse of `Normal` (bottom-most item will be considered "most recent")
-    pub order: FeedOrder,
+    /// We use the inner HTML of this node as the description
+    pub description_selector: Option<HtmlSelector>,
 
-    /// The maximum number of items to return
-    pub max_items: usize,
+    /// A css selector indicating which HTML node contains each items thumbnail.
+    ///
+    /// The url is read from `srcset`, `src`, `href` or `content`, see `media::media_url`
+    pub image_selector: Option<HtmlSelector>,
+
+    /// A css selector indicating which HTML node links to each items audio or video, e.g. a podcast episode
+    pub enclosure_selector: Option<HtmlSelector>,
+
+    /// A css selector indicating which HTML node contains each items author
+    pub author_selector: Option<HtmlSelector>,
+
+    /// A css selector indicating which HTML nodes contain each items categories (or tags).
+    ///
+    /// Unlike the other selectors every matching node is used, one category per node.
+    pub category_selector: Option<HtmlSelector>,
 }
 
+impl HtmlSelectors {
+    fn all(&self) -> impl Iterator<Item = &HtmlSelector> {
+        std::iter::once(&self.item_selector)
+            .chain(self.title_selector.as_ref())
+            .chain(self.link_selector.as_ref())
+            .chain(self.pub_date_selector.as_ref())
+            .chain(self.description_selector.as_ref())
+            .chain(self.image_selector.as_ref())
+            .chain(self.enclosure_selector.as_ref())
+            .chain(self.author_selector.as_ref())
+            .chain(self.category_selector.as_ref())
+    }
+
+    /// Whether any of these selectors are XPath expressions
+    pub fn needs_xpath(&self) -> bool {
+        self.all().any(HtmlSelector::is_xpath)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct JsonPaths {
+    /// A JSONPath indicating the array of items to turn into a feed, e.g. `$.data.posts[*]`
+    pub item_path: JsonPath,
+
+    /// A JSONPath, relative to each item, of the items title, e.g. `title`
+    pub title_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the items url.
+    ///
+    /// The url may be relative to the feed url.
+    pub link_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the date the item was published.
+    ///
+    /// This can be a human-readable date, an RFC 3339 date or a unix timestamp.
+    pub pub_date_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the items description
+    pub description_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the url of the items thumbnail
+    pub image_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the url of the items audio or video
+    pub enclosure_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the items author, e.g. `author.name`
+    pub author_path: Option<JsonPath>,
+
+    /// A JSONPath, relative to each item, of the items categories, e.g. `tags[*]`. Every match is used.
+    pub category_path: Option<JsonPath>,
+}
+
+/// The kind of document a feed is scraped from.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SourceKind { Html, Json, StructuredData, Sitemap, Feed }
+
 
 #[derive(Debug, PartialEq)]
 pub struct FeedRequestBuilder {
     pub name: String,
     pub url: Url,
-    pub item_selector: String,
+    pub item_selector: Option<String>,
     pub title_selector: Option<String>,
     pub link_selector: Option<String>,
     pub pub_date_selector: Option<String>,
+    pub description_selector: Option<String>,
+    pub image_selector: Option<String>,
+    pub enclosure_selector: Option<String>,
+    pub author_selector: Option<String>,
+    pub category_selector: Option<String>,
+    pub source: Option<SourceKind>,
+    pub fetch_titles: Option<bool>,
+    pub path_pattern: Option<String>,
     pub order: Option<FeedOrder>,
     pub max_items: Option<usize>,
+    pub title_rewrites: Vec<String>,
+    pub strip_query_params: Vec<String>,
+    pub host_rewrites: Vec<String>,
+    pub guid_strategy: Option<GuidStrategy>,
+    pub feed_description: Option<String>,
+    pub language: Option<String>,
+    pub image: Option<String>,
+    pub ttl: Option<u32>,
+    pub max_concurrent_requests: Option<usize>,
+    pub request_interval_ms: Option<u64>,
+    pub respect_robots_txt: Option<bool>,
+    pub encoding: Option<String>,
 }
 
 impl FeedRequestBuilder {
@@ -60,15 +207,196 @@ impl FeedRequestBuilder {
         FeedRequestBuilder {
             name: name.to_string(),
             url,
-            item_selector: item_selector.to_string(),
+            item_selector: Some(item_selector.to_string()),
             title_selector: None,
             link_selector: None,
             pub_date_selector: None,
+            description_selector: None,
+            image_selector: None,
+            enclosure_selector: None,
+            author_selector: None,
+            category_selector: None,
+            source: None,
+            fetch_titles: None,
+            path_pattern: None,
             order: None,
-            max_items: None
+            max_items: None,
+            title_rewrites: vec![],
+            strip_query_params: vec![],
+            host_rewrites: vec![],
+            guid_strategy: None,
+            feed_description: None,
+            language: None,
+            image: None,
+            ttl: None,
+            max_concurrent_requests: None,
+            request_interval_ms: None,
+            respect_robots_txt: None,
+            encoding: None,
         }
     }
 
+    /// Read a builder from the query parameters of an mk-rss lambda url, as produced by `to-rss-url`
+    pub fn from_query_pairs<I: IntoIterator<Item = (String, String)>>(pairs: I) -> anyhow::Result<FeedRequestBuilder> {
+        let mut params: HashMap<String, Vec<String>> = HashMap::new();
+        for (name, value) in pairs {
+            params.entry(name).or_default().push(value);
+        }
+
+        let get = |name: &str| params.get(name).and_then(|values| values.first()).cloned();
+        let get_all = |name: &str| params.get(name).cloned().unwrap_or_default();
+        let get_required = |name: &str| get(name).ok_or_else(|| anyhow::anyhow!("{} is required", name));
+
+        let name = get_required("name")?;
+        let url = get_required("url").and_then(|s| Url::parse(&s).context("Could not parse URL"))?;
+
+        let max_items = get("max_items")
+            .map(|s| s.parse::<usize>().context("max_items must be a number"))
+            .transpose()?;
+
+        let ttl = get("ttl")
+            .map(|s| s.parse::<u32>().context("ttl must be a number"))
+            .transpose()?;
+
+        let max_concurrent_requests = get("max_concurrent_requests")
+            .map(|s| s.parse::<usize>().context("max_concurrent_requests must be a number"))
+            .transpose()?;
+
+        let request_interval_ms = get("request_interval_ms")
+            .map(|s| s.parse::<u64>().context("request_interval_ms must be a number of milliseconds"))
+            .transpose()?;
+
+        let respect_robots_txt = get("respect_robots_txt")
+            .map(|s| s.parse::<bool>().context("respect_robots_txt must be true or false"))
+            .transpose()?;
+
+        let fetch_titles = get("fetch_titles")
+            .map(|s| s.parse::<bool>().context("fetch_titles must be true or false"))
+            .transpose()?;
+
+        Ok(FeedRequestBuilder {
+            name,
+            url,
+            item_selector: get("item_selector"),
+            title_selector: get("title_selector"),
+            link_selector: get("link_selector"),
+            pub_date_selector: get("pub_date_selector"),
+            description_selector: get("description_selector"),
+            image_selector: get("image_selector"),
+            enclosure_selector: get("enclosure_selector"),
+            author_selector: get("author_selector"),
+            category_selector: get("category_selector"),
+            source: get("source").as_deref().map(SourceKind::try_from).transpose()?,
+            fetch_titles,
+            path_pattern: get("path_pattern"),
+            order: get("order").as_deref().map(FeedOrder::try_from).transpose()?,
+            max_items,
+            title_rewrites: get_all("title_rewrite"),
+            strip_query_params: get_all("strip_query_param"),
+            host_rewrites: get_all("host_rewrite"),
+            guid_strategy: get("guid_strategy").as_deref().map(GuidStrategy::try_from).transpose()?,
+            feed_description: get("feed_description"),
+            language: get("language"),
+            image: get("image"),
+            ttl,
+            max_concurrent_requests,
+            request_interval_ms,
+            respect_robots_txt,
+            encoding: get("encoding"),
+        })
+    }
+
+    /// Read a builder from a feed definition: either a full mk-rss lambda url or just its query string
+    pub fn from_definition(definition: &str) -> anyhow::Result<FeedRequestBuilder> {
+        FeedRequestBuilder::from_query_pairs(definition_query_pairs(definition))
+    }
+
+    /// The query parameters of an mk-rss lambda url for this builder, the reverse of `from_query_pairs`
+    pub fn to_query_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = vec![
+            ("name", Some(self.name.clone())),
+            ("url", Some(self.url.to_string())),
+            ("source", self.source.map(|source| source.to_string())),
+            ("fetch_titles", self.fetch_titles.filter(|fetch_titles| *fetch_titles).map(|fetch_titles| fetch_titles.to_string())),
+            ("path_pattern", self.path_pattern.clone()),
+            ("item_selector", self.item_selector.clone()),
+            ("title_selector", self.title_selector.clone()),
+            ("link_selector", self.link_selector.clone()),
+            ("pub_date_selector", self.pub_date_selector.clone()),
+            ("description_selector", self.description_selector.clone()),
+            ("image_selector", self.image_selector.clone()),
+            ("enclosure_selector", self.enclosure_selector.clone()),
+            ("author_selector", self.author_selector.clone()),
+            ("category_selector", self.category_selector.clone()),
+            ("order", self.order.map(|order| order.to_string())),
+            ("max_items", self.max_items.map(|max_items| max_items.to_string())),
+            ("guid_strategy", self.guid_strategy.as_ref().map(GuidStrategy::to_string)),
+        ];
+
+        pairs.extend(self.title_rewrites.iter().map(|title_rewrite| ("title_rewrite", Some(title_rewrite.clone()))));
+        pairs.extend(self.strip_query_params.iter().map(|strip_query_param| ("strip_query_param", Some(strip_query_param.clone()))));
+        pairs.extend(self.host_rewrites.iter().map(|host_rewrite| ("host_rewrite", Some(host_rewrite.clone()))));
+
+        pairs.extend(vec![
+            ("feed_description", self.feed_description.clone()),
+            ("language", self.language.clone()),
+            ("image", self.image.clone()),
+            ("ttl", self.ttl.map(|ttl| ttl.to_string())),
+            ("max_concurrent_requests", self.max_concurrent_requests.map(|max_concurrent_requests| max_concurrent_requests.to_string())),
+            ("request_interval_ms", self.request_interval_ms.map(|request_interval_ms| request_interval_ms.to_string())),
+            ("respect_robots_txt", self.respect_robots_txt.map(|respect_robots_txt| respect_robots_txt.to_string())),
+            ("encoding", self.encoding.clone()),
+        ]);
+
+        pairs
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+            .collect()
+    }
+
+    /// The `mk-rss-cli` options that recreate this builder, named after `to_query_pairs`, leaving out
+    /// any that match their default. Flags like `--fetch-titles` have no value.
+    pub fn to_cli_arguments(&self) -> Vec<(String, Option<String>)> {
+        self.to_query_pairs()
+            .into_iter()
+            .filter(|(name, value)| !matches!(
+                (name.as_str(), value.as_str()),
+                ("source", "html") | ("order", "normal") | ("max_items", "30") | ("guid_strategy", "url")
+            ))
+            .map(|(name, value)| match name.as_str() {
+                "fetch_titles" => ("fetch-titles".to_string(), None),
+                _ => (name.replace('_', "-"), Some(value)),
+            })
+            .collect()
+    }
+
+    /// The `mk-rss-cli` options that recreate this builder, quoted for a POSIX shell
+    pub fn to_cli_command(&self) -> String {
+        self.to_cli_arguments()
+            .into_iter()
+            .map(|(flag, value)| match value {
+                Some(value) => format!("--{} {}", flag, shell_quote(&value)),
+                None => format!("--{}", flag),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Encode this builder as a compact token signed with `secret`, which the lambda serves at `/f/<token>`
+    pub fn to_token(&self, secret: &[u8]) -> String {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(self.to_query_pairs())
+            .finish();
+
+        token::encode(&query, secret)
+    }
+
+    /// Read a builder from a token produced by `to_token` with the same `secret`
+    pub fn from_token(token: &str, secret: &[u8]) -> anyhow::Result<FeedRequestBuilder> {
+        let query = token::decode(token, secret)?;
+        FeedRequestBuilder::from_query_pairs(form_urlencoded::parse(query.as_bytes()).into_owned())
+    }
+
     pub fn maybe_title_selector<S: Into<String>>(&mut self, selector: Option<S>) -> &mut Self {
         self.title_selector = selector.map(|s| s.into());
         self
@@ -88,6 +416,48 @@ impl FeedRequestBuilder {
         self
     }
 
+    pub fn description_selector<S: Into<String>>(&mut self, selector: S) -> &mut Self {
+        self.description_selector = Some(selector.into());
+        self
+    }
+
+    pub fn image_selector<S: Into<String>>(&mut self, selector: S) -> &mut Self {
+        self.image_selector = Some(selector.into());
+        self
+    }
+
+    pub fn enclosure_selector<S: Into<String>>(&mut self, selector: S) -> &mut Self {
+        self.enclosure_selector = Some(selector.into());
+        self
+    }
+
+    pub fn author_selector<S: Into<String>>(&mut self, selector: S) -> &mut Self {
+        self.author_selector = Some(selector.into());
+        self
+    }
+
+    pub fn category_selector<S: Into<String>>(&mut self, selector: S) -> &mut Self {
+        self.category_selector = Some(selector.into());
+        self
+    }
+
+    pub fn source<S: Into<SourceKind>>(&mut self, source: S) -> &mut Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Whether the sitemap source should fetch each page to find its title
+    pub fn fetch_titles(&mut self, fetch_titles: bool) -> &mut Self {
+        self.fetch_titles = Some(fetch_titles);
+        self
+    }
+
+    /// A regex the path of each url in the sitemap must match to become an item
+    pub fn path_pattern<S: Into<String>>(&mut self, pattern: S) -> &mut Self {
+        self.path_pattern = Some(pattern.into());
+        self
+    }
+
     pub fn order<O: Into<FeedOrder>>(&mut self, order: O) -> &mut Self {
         self.order = Some(order.into());
         self
@@ -98,46 +468,253 @@ impl FeedRequestBuilder {
         self
     }
 
-    pub fn build(&self) -> anyhow::Result<FeedRequest> {
-        let item_selector = Selector::parse(&self.item_selector)
-            .map_err(|e| anyhow::anyhow!("Could not parse item_selector: {:?}", e))?;
+    /// Add a `PATTERN=>REPLACEMENT` regex rewrite for each items title
+    pub fn title_rewrite<S: Into<String>>(&mut self, rule: S) -> &mut Self {
+        self.title_rewrites.push(rule.into());
+        self
+    }
 
-        let title_selector = self.title_selector
-            .as_ref()
-            .map(|s| Selector::parse(s))
-            .transpose()
-            .map_err(|e| anyhow::anyhow!("Could not parse title_selector: {:?}", e))?;
+    /// Remove the query parameter `name` from each items url. `name` may end with `*` to match a prefix
+    pub fn strip_query_param<S: Into<String>>(&mut self, name: S) -> &mut Self {
+        self.strip_query_params.push(name.into());
+        self
+    }
 
-        let link_selector = self.link_selector
-            .as_ref()
-            .map(|s| Selector::parse(s))
-            .transpose()
-            .map_err(|e| anyhow::anyhow!("Could not parse link_selector: {:?}", e))?;
+    /// Add a `FROM_HOST=>TO_HOST` rewrite for each items url
+    pub fn host_rewrite<S: Into<String>>(&mut self, rule: S) -> &mut Self {
+        self.host_rewrites.push(rule.into());
+        self
+    }
 
-        let pub_date_selector = self.pub_date_selector
-            .as_ref()
-            .map(|s| Selector::parse(s))
-            .transpose()
-            .map_err(|e| anyhow::anyhow!("Could not parse pub_date_selector: {:?}", e))?;
+    pub fn guid_strategy<G: Into<GuidStrategy>>(&mut self, guid_strategy: G) -> &mut Self {
+        self.guid_strategy = Some(guid_strategy.into());
+        self
+    }
+
+    /// The description of the feed itself, rather than of each item
+    pub fn feed_description<S: Into<String>>(&mut self, description: S) -> &mut Self {
+        self.feed_description = Some(description.into());
+        self
+    }
+
+    pub fn language<S: Into<String>>(&mut self, language: S) -> &mut Self {
+        self.language = Some(language.into());
+        self
+    }
+
+    /// The url of an image representing the feed
+    pub fn image<S: Into<String>>(&mut self, image: S) -> &mut Self {
+        self.image = Some(image.into());
+        self
+    }
+
+    /// How many minutes readers should cache the feed for
+    pub fn ttl(&mut self, ttl: u32) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// How many requests may be made to the site at once while fetching this feed
+    pub fn max_concurrent_requests(&mut self, max_concurrent_requests: usize) -> &mut Self {
+        self.max_concurrent_requests = Some(max_concurrent_requests);
+        self
+    }
+
+    /// How many milliseconds to wait between requests to the site while fetching this feed
+    pub fn request_interval_ms(&mut self, request_interval_ms: u64) -> &mut Self {
+        self.request_interval_ms = Some(request_interval_ms);
+        self
+    }
+
+    /// Whether to check the site's robots.txt before fetching this feed
+    pub fn respect_robots_txt(&mut self, respect_robots_txt: bool) -> &mut Self {
+        self.respect_robots_txt = Some(respect_robots_txt);
+        self
+    }
+
+    /// The encoding of the page, e.g. `shift_jis`, used instead of whatever the page declares
+    pub fn encoding<S: Into<String>>(&mut self, encoding: S) -> &mut Self {
+        self.encoding = Some(encoding.into());
+        self
+    }
+
+    pub fn build(&self) -> anyhow::Result<FeedRequest> {
+        let source = match self.source.unwrap_or(SourceKind::Html) {
+            SourceKind::Html => FeedSource::Html(self.build_html_selectors()?),
+            SourceKind::Json => FeedSource::Json(self.build_json_paths()?),
+            SourceKind::StructuredData => FeedSource::StructuredData,
+            SourceKind::Sitemap => FeedSource::Sitemap(self.build_sitemap_options()?),
+            SourceKind::Feed => FeedSource::Feed,
+        };
 
         let order = self.order.unwrap_or(FeedOrder::Normal);
 
         let max_items = self.max_items.unwrap_or(30);
         let max_items = cmp::min(max_items, 30);
 
+        let title_rewrites = self.title_rewrites
+            .iter()
+            .map(|s| s.parse::<TitleRewrite>())
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Could not parse title_rewrite: {}", e))?;
+
+        let host_rewrites = self.host_rewrites
+            .iter()
+            .map(|s| s.parse::<HostRewrite>())
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Could not parse host_rewrite: {}", e))?;
+
+        let guid_strategy = self.guid_strategy.clone().unwrap_or(GuidStrategy::Url);
+
+        // Upstream feed items don't have attributes, `attribute:guid` keeps their `<guid>` or `<id>`
+        if let (FeedSource::Feed, Some(attribute)) = (&source, guid_strategy.attribute()) {
+            if attribute != "guid" {
+                return Err(anyhow::anyhow!("guid_strategy attribute:{} isn't available with source feed, only attribute:guid", attribute));
+            }
+        }
+
+        let image = self.image
+            .as_ref()
+            .map(|image| self.url.join(image))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Could not parse image: {}", e))?;
+
+        let channel = ChannelMetadata {
+            description: self.feed_description.clone(),
+            language: self.language.clone(),
+            image,
+            ttl: self.ttl,
+        };
+
+        if self.max_concurrent_requests == Some(0) {
+            return Err(anyhow::anyhow!("max_concurrent_requests must be at least 1"));
+        }
+
+        let host_limits = HostLimits {
+            max_concurrent_requests: self.max_concurrent_requests,
+            request_interval: self.request_interval_ms.map(Duration::from_millis),
+            respect_robots_txt: self.respect_robots_txt,
+        };
+
+        let encoding = self.encoding
+            .as_ref()
+            .map(|label| Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| anyhow::anyhow!("{} is not a valid encoding", label)))
+            .transpose()?;
+
+        let rewrite = RewriteRules {
+            title_rewrites,
+            strip_query_params: self.strip_query_params.clone(),
+            host_rewrites,
+        };
+
         Ok(FeedRequest {
             name: self.name.clone(),
             url: self.url.clone(),
-            item_selector,
-            title_selector,
-            link_selector,
-            pub_date_selector,
+            source,
             order,
-            max_items
+            max_items,
+            rewrite,
+            guid_strategy,
+            channel,
+            host_limits,
+            encoding,
+        })
+    }
+
+    fn required_item_selector(&self) -> anyhow::Result<&str> {
+        self.item_selector
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("item_selector is required"))
+    }
+
+    fn build_sitemap_options(&self) -> anyhow::Result<SitemapOptions> {
+        let path_pattern = self.path_pattern
+            .as_ref()
+            .map(|pattern| Regex::new(pattern).map(PathPattern))
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Could not parse path_pattern: {}", e))?;
+
+        Ok(SitemapOptions {
+            path_pattern,
+            fetch_titles: self.fetch_titles.unwrap_or(false),
+        })
+    }
+
+    fn build_html_selectors(&self) -> anyhow::Result<HtmlSelectors> {
+        let parse = |name: &str, selector: &str| {
+            selector
+                .parse::<HtmlSelector>()
+                .map_err(|e| anyhow::anyhow!("Could not parse {}: {}", name, e))
+        };
+
+        Ok(HtmlSelectors {
+            item_selector: parse("item_selector", self.required_item_selector()?)?,
+            title_selector: self.title_selector.as_ref().map(|s| parse("title_selector", s)).transpose()?,
+            link_selector: self.link_selector.as_ref().map(|s| parse("link_selector", s)).transpose()?,
+            pub_date_selector: self.pub_date_selector.as_ref().map(|s| parse("pub_date_selector", s)).transpose()?,
+            description_selector: self.description_selector.as_ref().map(|s| parse("description_selector", s)).transpose()?,
+            image_selector: self.image_selector.as_ref().map(|s| parse("image_selector", s)).transpose()?,
+            enclosure_selector: self.enclosure_selector.as_ref().map(|s| parse("enclosure_selector", s)).transpose()?,
+            author_selector: self.author_selector.as_ref().map(|s| parse("author_selector", s)).transpose()?,
+            category_selector: self.category_selector.as_ref().map(|s| parse("category_selector", s)).transpose()?,
+        })
+    }
+
+    /// In JSON mode each selector is a JSONPath. For convenience paths don't need to start with `$`,
+    /// so `title` and `$.title` are equivalent.
+    fn build_json_paths(&self) -> anyhow::Result<JsonPaths> {
+        let parse = |name: &str, path: &str| {
+            let path = if path.starts_with('$') {
+                path.to_string()
+            } else if path.starts_with('[') {
+                format!("${}", path)
+            } else {
+                format!("$.{}", path)
+            };
+
+            JsonPath::parse(&path)
+                .map_err(|e| anyhow::anyhow!("Could not parse {}: {}", name, e))
+        };
+
+        Ok(JsonPaths {
+            item_path: parse("item_selector", self.required_item_selector()?)?,
+            title_path: self.title_selector.as_ref().map(|s| parse("title_selector", s)).transpose()?,
+            link_path: self.link_selector.as_ref().map(|s| parse("link_selector", s)).transpose()?,
+            pub_date_path: self.pub_date_selector.as_ref().map(|s| parse("pub_date_selector", s)).transpose()?,
+            description_path: self.description_selector.as_ref().map(|s| parse("description_selector", s)).transpose()?,
+            image_path: self.image_selector.as_ref().map(|s| parse("image_selector", s)).transpose()?,
+            enclosure_path: self.enclosure_selector.as_ref().map(|s| parse("enclosure_selector", s)).transpose()?,
+            author_path: self.author_selector.as_ref().map(|s| parse("author_selector", s)).transpose()?,
+            category_path: self.category_selector.as_ref().map(|s| parse("category_selector", s)).transpose()?,
         })
     }
 }
 
+/// The query parameters of a feed definition: either a full mk-rss lambda url or just its query string
+pub fn definition_query_pairs(definition: &str) -> Vec<(String, String)> {
+    let definition = definition.trim();
+
+    match Url::parse(definition) {
+        Ok(url) => url.query_pairs().into_owned().collect(),
+        Err(_) => form_urlencoded::parse(definition.trim_start_matches('?').as_bytes()).into_owned().collect(),
+    }
+}
+
+/// Parse a list of feed definitions, one per line (see `FeedRequestBuilder::from_definition`).
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub fn parse_feed_definitions(text: &str) -> anyhow::Result<Vec<FeedRequest>> {
+    text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            FeedRequestBuilder::from_definition(line)
+                .and_then(|builder| builder.build())
+                .with_context(|| format!("Could not parse the feed definition on line {}", index + 1))
+        })
+        .collect()
+}
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum FeedOrder { Normal, Reversed }
@@ -149,7 +726,7 @@ impl TryFrom<&str> for FeedOrder {
         match value {
             "normal" => Ok(FeedOrder::Normal),
             "reversed" => Ok(FeedOrder::Reversed),
-            _ => Err(anyhow::anyhow!("{} is not a valid order (valid orders are 'normal' and 'reversed')"))
+            _ => Err(anyhow::anyhow!("{} is not a valid order (valid orders are 'normal' and 'reversed')", value))
         }
     }
 }
@@ -162,11 +739,139 @@ impl FromStr for FeedOrder {
     }
 }
 
-impl ToString for FeedOrder {
-    fn to_string(&self) -> String {
+impl fmt::Display for FeedOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeedOrder::Normal => write!(f, "normal"),
+            FeedOrder::Reversed => write!(f, "reversed"),
+        }
+    }
+}
+
+impl TryFrom<&str> for SourceKind {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "html" => Ok(SourceKind::Html),
+            "json" => Ok(SourceKind::Json),
+            "structured-data" => Ok(SourceKind::StructuredData),
+            "sitemap" => Ok(SourceKind::Sitemap),
+            "feed" => Ok(SourceKind::Feed),
+            _ => Err(anyhow::anyhow!("{} is not a valid source (valid sources are 'html', 'json', 'structured-data', 'sitemap' and 'feed')", value))
+        }
+    }
+}
+
+impl FromStr for SourceKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+impl fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            FeedOrder::Normal => "normal".to_string(),
-            FeedOrder::Reversed => "reversed".to_string(),
+            SourceKind::Html => write!(f, "html"),
+            SourceKind::Json => write!(f, "json"),
+            SourceKind::StructuredData => write!(f, "structured-data"),
+            SourceKind::Sitemap => write!(f, "sitemap"),
+            SourceKind::Feed => write!(f, "feed"),
         }
     }
 }
+
+/// Quote `value` for a POSIX shell, unless it's plain enough not to need it
+fn shell_quote(value: &str) -> String {
+    let is_plain = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+
+    if is_plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip_builder() -> FeedRequestBuilder {
+        let mut builder = FeedRequestBuilder::new("Example & Co", Url::parse("https://example.com/news").unwrap(), "article");
+        builder
+            .title_selector("h2 a")
+            .category_selector(".tags a")
+            .order(FeedOrder::Reversed)
+            .max_items(10_usize)
+            .title_rewrite("Read more »=>")
+            .strip_query_param("utm_*")
+            .guid_strategy(GuidStrategy::Hash)
+            .ttl(60)
+            .request_interval_ms(1000)
+            .respect_robots_txt(false)
+            .encoding("windows-1251");
+        builder.fetch_titles = Some(true);
+        builder.path_pattern = Some("^/news/".into());
+        builder
+    }
+
+    #[test]
+    pub fn to_cli_arguments_should_round_trip_every_parameter() {
+        let builder = round_trip_builder();
+
+        let pairs = builder
+            .to_cli_arguments()
+            .into_iter()
+            .map(|(flag, value)| (flag.replace('-', "_"), value.unwrap_or_else(|| "true".to_string())));
+
+        assert_eq!(FeedRequestBuilder::from_query_pairs(pairs).unwrap(), builder);
+
+        let mut defaults = FeedRequestBuilder::new("Example", Url::parse("https://example.com/").unwrap(), "article");
+        defaults.source(SourceKind::Html).order(FeedOrder::Normal).max_items(30_usize).guid_strategy(GuidStrategy::Url);
+        assert_eq!(defaults.to_cli_command(), "--name Example --url https://example.com/ --item-selector article");
+    }
+
+    #[test]
+    pub fn shell_quote_should_round_trip_through_a_shell() {
+        for value in &["plain", "", "Example & Co", "it's", r"\s+\| Example$=>", "a\nb", "'", "$HOME `id`"] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", shell_quote(value)))
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), *value);
+        }
+    }
+
+    #[test]
+    pub fn to_token_should_round_trip_every_parameter() {
+        let builder = round_trip_builder();
+        let token = builder.to_token(b"secret");
+
+        assert_eq!(FeedRequestBuilder::from_token(&token, b"secret").unwrap(), builder);
+        assert!(FeedRequestBuilder::from_token(&token, b"another secret").is_err());
+    }
+
+    #[test]
+    pub fn sitemap_sources_should_match_paths_against_path_pattern() {
+        let pairs = |path_pattern: &str| vec![
+            ("name".to_string(), "Example".to_string()),
+            ("url".to_string(), "https://example.com/sitemap.xml".to_string()),
+            ("source".to_string(), "sitemap".to_string()),
+            ("item_selector".to_string(), "article".to_string()),
+            ("path_pattern".to_string(), path_pattern.to_string()),
+        ];
+
+        let request = FeedRequestBuilder::from_query_pairs(pairs("^/blog/")).unwrap().build().unwrap();
+        match request.source {
+            FeedSource::Sitemap(options) => assert_eq!(options.path_pattern, Some(PathPattern(Regex::new("^/blog/").unwrap()))),
+            source => panic!("expected a sitemap source, got {:?}", source),
+        }
+
+        let error = FeedRequestBuilder::from_query_pairs(pairs("(")).unwrap().build().unwrap_err();
+        assert!(error.to_string().starts_with("Could not parse path_pattern"));
+    }
+}
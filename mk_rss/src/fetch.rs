@@ -1,14 +1,128 @@
 use anyhow::Context;
-use reqwest::Url;
+use encoding_rs::Encoding;
+use reqwest::redirect;
+use reqwest::{Method, Response, Url};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 
-pub async fn fetch_url(url: Url) -> anyhow::Result<String> {
+use super::charset;
+use super::rate_limit::{HostLimits, HostPermit, HOST_LIMITER};
+use super::robots::{self, RobotsDisallowed, RobotsTxt};
+use super::url_policy::{UrlPolicy, URL_POLICY};
+
+/// Some sites die if we don't provide a user agent, let's just give them the chrome one.
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.36";
+
+/// How many redirects we follow before giving up
+const MAX_REDIRECTS: usize = 10;
+
+/// How many clients we keep for connecting to hosts at the addresses we checked, see `CLIENTS`
+const MAX_CLIENTS: usize = 64;
+
+/// The clients we send requests with, shared so connections are reused between requests.
+///
+/// reqwest can't use a resolver of our own, so a host is only connected to through a client that
+/// pins it to the address `UrlPolicy` checked, keyed by that host and address. Urls that don't need
+/// pinning (IP addresses, or when private addresses are allowed) share the client keyed by `None`.
+static CLIENTS: Lazy<Mutex<HashMap<Option<PinnedHost>, reqwest::Client>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A domain and the address `UrlPolicy` checked it resolves to
+type PinnedHost = (String, SocketAddr);
+
+/// The most bytes we'll download from a single url, so a url pointing at a huge file can't exhaust our memory
+const MAX_BODY_BYTES_VARIABLE: &str = "MK_RSS_MAX_BODY_BYTES";
+
+const DEFAULT_MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+
+/// Top-level MIME types that can never be made into a feed
+const BINARY_TOP_LEVEL_TYPES: &[&str] = &["audio", "font", "image", "model", "video"];
+
+/// Other MIME types that can never be made into a feed.
+///
+/// Plenty of servers send `application/octet-stream` for anything they don't recognise, feeds
+/// included, so we leave it to the size cap rather than refusing it.
+const BINARY_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/pdf",
+    "application/vnd.rar",
+    "application/wasm",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-gzip",
+    "application/x-msdownload",
+    "application/x-rar-compressed",
+    "application/x-tar",
+    "application/zip",
+];
+
+/// The error returned when a response is bigger than we're willing to download
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ResponseTooLarge {
+    pub url: Url,
+    pub max_bytes: usize,
+}
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is larger than the maximum of {} bytes (see {})", self.url, self.max_bytes, MAX_BODY_BYTES_VARIABLE)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+/// The error returned when a response is a file, like an image or archive, rather than something we can read items from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BinaryContentType {
+    pub url: Url,
+    pub content_type: String,
+}
+
+impl fmt::Display for BinaryContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is {}, which can't be made into a feed", self.url, self.content_type)
+    }
+}
+
+impl std::error::Error for BinaryContentType {}
+
+/// What a HEAD request tells us about a resource
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct ResourceInfo {
+    /// The MIME type of the resource, without any parameters
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+}
+
+/// A response body as the server sent it, which we only decode once we know what encoding to use
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Page {
+    pub body: Vec<u8>,
+
+    /// The Content-Type header, including any charset
+    pub content_type: Option<String>,
+}
+
+impl Page {
+    /// The body decoded as text, see `charset::decode`
+    pub fn text(&self, encoding_override: Option<&'static Encoding>) -> String {
+        charset::decode(&self.body, self.content_type.as_deref(), encoding_override)
+    }
+}
+
+pub async fn fetch_url(url: Url, limits: &HostLimits) -> anyhow::Result<Page> {
+    // Before looking in the cache, which other feeds that don't respect robots.txt share
+    let limits = check_robots_txt(&url, limits).await?;
     let cache_path = calculate_cache_path(&url);
 
     // Currently let's cache for 30 minutes and see how things go
@@ -18,7 +132,7 @@ pub async fn fetch_url(url: Url) -> anyhow::Result<String> {
         return Ok(cache_result)
     }
 
-    let response = fetch_from_web(url).await?;
+    let response = fetch_from_web(url, &limits).await?;
     cache_response(&cache_path, &response)?;
     Ok(response)
 }
@@ -26,7 +140,7 @@ pub async fn fetch_url(url: Url) -> anyhow::Result<String> {
 fn fetch_from_cache(
     cache_path: &Path,
     cache_timeout: Duration
-) -> anyhow::Result<Option<String>> {
+) -> anyhow::Result<Option<Page>> {
     let cache_file = File::open(cache_path)
         .context(format!("failed to open file: {:?}", cache_path))?;
     let modified = cache_file.metadata()?.modified()?;
@@ -36,10 +150,14 @@ fn fetch_from_cache(
 
     if cache_age < cache_timeout {
         let mut buf_reader = BufReader::new(cache_file);
-        let mut cache = String::new();
-        buf_reader.read_to_string(&mut cache)?;
+        let mut content_type = String::new();
+        buf_reader.read_line(&mut content_type)?;
+
+        let mut body = vec![];
+        buf_reader.read_to_end(&mut body)?;
 
-        Ok(Some(cache))
+        let content_type = Some(content_type.trim().to_string()).filter(|content_type| !content_type.is_empty());
+        Ok(Some(Page { body, content_type }))
     } else {
         Ok(None)
     }
@@ -51,29 +169,302 @@ fn calculate_cache_path(url: &Url) -> PathBuf {
     url.hash(&mut hasher);
     let hash = hasher.finish();
 
-    let cache_file_name = format!("mk-rss-{}", hash);
+    // Named differently to when we cached decoded text, so we don't misread those files
+    let cache_file_name = format!("mk-rss-page-{}", hash);
     Path::new("/tmp/").join(cache_file_name)
 }
 
-async fn fetch_from_web(url: Url) -> anyhow::Result<String> {
-    // Some sites die if we don't provide a user agent, let's just give them the chrome one.
-    let chrome_user_agent = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/58.0.3029.110 Safari/537.36";
+/// Fetch `url` once `limits` allow, which must already have been checked against robots.txt
+async fn fetch_from_web(url: Url, limits: &HostLimits) -> anyhow::Result<Page> {
+    let _permit = HOST_LIMITER.acquire(&url, limits).await;
+    let response = send(Method::GET, url.clone(), &URL_POLICY).await?;
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.trim().to_string());
+
+    check_content_type(&url, content_type.as_deref())?;
+    let body = read_body(response, &url, max_body_bytes()).await?;
+
+    Ok(Page { body, content_type })
+}
+
+/// Find the type and size of the resource at `url` without downloading it
+pub async fn fetch_head(url: Url, limits: &HostLimits) -> anyhow::Result<ResourceInfo> {
+    let _permit = permit(&url, limits).await?;
+    let response = send(Method::HEAD, url, &URL_POLICY)
+        .await?
+        .error_for_status()?;
+
+    let header = |name: reqwest::header::HeaderName| response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string());
+
+    Ok(ResourceInfo {
+        content_type: header(reqwest::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.split(';').next().map(|mime_type| mime_type.trim().to_lowercase()))
+            .filter(|mime_type| !mime_type.is_empty()),
+        content_length: header(reqwest::header::CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+    })
+}
+
+/// Refuse `content_type` if it's a binary format, there's no point downloading it
+fn check_content_type(url: &Url, content_type: Option<&str>) -> Result<(), BinaryContentType> {
+    let mime_type = match content_type.and_then(|content_type| content_type.split(';').next()) {
+        Some(mime_type) => mime_type.trim().to_lowercase(),
+        None => return Ok(()),
+    };
 
-    let client = reqwest::Client::new();
-    let response = client
-        .get(url)
-        .header(reqwest::header::USER_AGENT, chrome_user_agent)
-        .send()
-        .await?;
+    let top_level_type = mime_type.split('/').next().unwrap_or_default();
+    if BINARY_TOP_LEVEL_TYPES.contains(&top_level_type) || BINARY_TYPES.contains(&mime_type.as_str()) {
+        return Err(BinaryContentType { url: url.clone(), content_type: mime_type });
+    }
+
+    Ok(())
+}
+
+/// The body of `response`, giving up as soon as it's bigger than `max_bytes` rather than downloading the lot
+async fn read_body(mut response: Response, url: &Url, max_bytes: usize) -> anyhow::Result<Vec<u8>> {
+    let too_large = || ResponseTooLarge { url: url.clone(), max_bytes };
+
+    if response.content_length().map(|length| length > max_bytes as u64).unwrap_or(false) {
+        return Err(too_large().into());
+    }
 
-    let body = response.text().await?;
+    let mut body = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(too_large().into());
+        }
+
+        body.extend_from_slice(&chunk);
+    }
 
     Ok(body)
 }
 
-fn cache_response(cache_path: &Path, body: &String) -> anyhow::Result<()> {
+/// The most bytes we'll download from a single url, as configured by `MK_RSS_MAX_BODY_BYTES`
+fn max_body_bytes() -> usize {
+    env::var(MAX_BODY_BYTES_VARIABLE)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+/// Wait for our turn to fetch `url`, first checking the site's robots.txt allows it if `limits` say to
+async fn permit(url: &Url, limits: &HostLimits) -> anyhow::Result<HostPermit<'static>> {
+    let limits = check_robots_txt(url, limits).await?;
+    Ok(HOST_LIMITER.acquire(url, &limits).await)
+}
+
+/// Check the site's robots.txt allows fetching `url` if `limits` say to, returning `limits`
+/// slowed down to its `Crawl-delay`
+async fn check_robots_txt(url: &Url, limits: &HostLimits) -> anyhow::Result<HostLimits> {
+    if !robots::should_respect(limits) {
+        return Ok(*limits);
+    }
+
+    let robots_txt = fetch_robots_txt(url, limits).await?;
+    if !robots_txt.is_allowed(url) {
+        return Err(RobotsDisallowed { url: url.clone() }.into());
+    }
+
+    Ok(match robots_txt.crawl_delay {
+        Some(crawl_delay) => HostLimits { request_interval: Some(HOST_LIMITER.request_interval(limits).max(crawl_delay)), ..*limits },
+        None => *limits,
+    })
+}
+
+/// The robots.txt of the site hosting `url`.
+///
+/// A site without one allows everything, while one we can't reach because of a server error allows nothing (RFC 9309).
+async fn fetch_robots_txt(url: &Url, limits: &HostLimits) -> anyhow::Result<RobotsTxt> {
+    let robots_url = url.join("/robots.txt")?;
+    if let Some(robots_txt) = robots::cached(&robots_url) {
+        return Ok(robots_txt);
+    }
+
+    let response = {
+        let _permit = HOST_LIMITER.acquire(&robots_url, limits).await;
+        send(Method::GET, robots_url.clone(), &URL_POLICY)
+            .await
+            .context(format!("Could not fetch {}", robots_url))?
+    };
+
+    let robots_txt = if response.status().is_success() {
+        let body = read_body(response, &robots_url, max_body_bytes()).await?;
+        RobotsTxt::parse(&String::from_utf8_lossy(&body), &robots::user_agent())
+    } else if response.status().is_client_error() {
+        RobotsTxt::default()
+    } else {
+        return Ok(RobotsTxt::disallow_all());
+    };
+
+    robots::cache(&robots_url, &robots_txt);
+    Ok(robots_txt)
+}
+
+/// Send a `method` request for `url`, refusing any url `policy` doesn't allow.
+///
+/// We follow redirects ourselves so that every url we're redirected to is checked too, and connect
+/// to the address the policy checked so a host can't change what it resolves to in between.
+async fn send(method: Method, url: Url, policy: &UrlPolicy) -> anyhow::Result<Response> {
+    let mut url = url;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let pinned = match (policy.check(&url).await?, url.domain()) {
+            (Some(address), Some(domain)) => Some((domain.to_string(), address)),
+            _ => None,
+        };
+
+        let response = client(pinned)?
+            .request(method.clone(), url.clone())
+            .header(reqwest::header::USER_AGENT, USER_AGENT)
+            .send()
+            .await?;
+
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .filter(|_| response.status().is_redirection());
+
+        match location {
+            Some(location) => url = url.join(location).context(format!("{} redirected to an invalid url: {}", url, location))?,
+            None => return Ok(response),
+        }
+    }
+
+    Err(anyhow::anyhow!("{} redirected more than {} times", url, MAX_REDIRECTS))
+}
+
+/// The shared client for connecting to `pinned`'s host at its address, or to any url that isn't pinned
+fn client(pinned: Option<PinnedHost>) -> anyhow::Result<reqwest::Client> {
+    let mut clients = CLIENTS.lock().expect("clients lock poisoned");
+    if let Some(client) = clients.get(&pinned) {
+        return Ok(client.clone());
+    }
+
+    let mut builder = reqwest::Client::builder().redirect(redirect::Policy::none());
+    if let Some((domain, address)) = &pinned {
+        builder = builder.resolve(domain, *address);
+    }
+
+    // Hosts come and go, so rather than track which were used last we start again when full
+    if clients.len() >= MAX_CLIENTS {
+        clients.clear();
+    }
+
+    let client = builder.build()?;
+    clients.insert(pinned, client.clone());
+    Ok(client)
+}
+
+/// Cache the Content-Type header on the first line, followed by the body exactly as we received it
+fn cache_response(cache_path: &Path, page: &Page) -> anyhow::Result<()> {
     let mut cache_file = File::create(cache_path)
         .context(format!("failed to open file: {:?}", cache_path))?;
-    cache_file.write_all(body.as_bytes())?;
+    writeln!(cache_file, "{}", page.content_type.as_deref().unwrap_or_default())?;
+    cache_file.write_all(&page.body)?;
     Ok(())
 }
+
+/// Cache `page` as the response for `url`, so tests elsewhere can fetch it without a server
+#[cfg(test)]
+pub fn cache_for_test(url: &Url, page: &Page) {
+    cache_response(&calculate_cache_path(url), page).expect("failed to cache page");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    pub async fn send_should_check_every_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/feed", listener.local_addr().unwrap())).unwrap();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _request = socket.read(&mut [0; 4096]).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 302 Found\r\nlocation: http://metadata.internal/latest/\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let policy = UrlPolicy {
+            allowed_hosts: vec![],
+            denied_hosts: vec!["metadata.internal".into()],
+            allow_private_addresses: true,
+        };
+
+        let error = send(Method::GET, url, &policy).await.unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to fetch http://metadata.internal/latest/: metadata.internal is denied");
+
+        let error = send(Method::GET, Url::parse("http://127.0.0.1/").unwrap(), &UrlPolicy::default()).await.unwrap_err();
+        assert_eq!(error.to_string(), "Refusing to fetch http://127.0.0.1/: 127.0.0.1 isn't a public address");
+    }
+
+    #[tokio::test]
+    pub async fn read_body_should_stop_at_the_maximum_size() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/huge.html", listener.local_addr().unwrap())).unwrap();
+
+        tokio::spawn(async move {
+            // No content-length, so we only find out how big the body is by reading it
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _request = socket.read(&mut [0; 4096]).await.unwrap();
+            socket.write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/html\r\nconnection: close\r\n\r\n").await.unwrap();
+            for _ in 0..64 {
+                if socket.write_all(&[b'a'; 1024]).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let policy = UrlPolicy { allow_private_addresses: true, ..UrlPolicy::default() };
+        let response = send(Method::GET, url.clone(), &policy).await.unwrap();
+        let error = read_body(response, &url, 16 * 1024).await.unwrap_err();
+
+        assert_eq!(error.downcast_ref::<ResponseTooLarge>(), Some(&ResponseTooLarge { url, max_bytes: 16 * 1024 }));
+    }
+
+    #[tokio::test]
+    pub async fn fetch_url_should_check_robots_txt_before_the_cache() {
+        let url = Url::parse("https://robots.example.com/private/feed").unwrap();
+        cache_response(&calculate_cache_path(&url), &Page { body: b"<rss></rss>".to_vec(), content_type: None }).unwrap();
+        robots::cache(&url.join("/robots.txt").unwrap(), &RobotsTxt::disallow_all());
+
+        let ignore_robots_txt = HostLimits { respect_robots_txt: Some(false), ..HostLimits::default() };
+        assert_eq!(fetch_url(url.clone(), &ignore_robots_txt).await.unwrap().body, b"<rss></rss>");
+
+        let respect_robots_txt = HostLimits { respect_robots_txt: Some(true), ..HostLimits::default() };
+        let error = fetch_url(url.clone(), &respect_robots_txt).await.unwrap_err();
+        assert_eq!(error.downcast_ref::<RobotsDisallowed>(), Some(&RobotsDisallowed { url }));
+    }
+
+    #[test]
+    pub fn check_content_type_should_refuse_binary_types() {
+        let url = Url::parse("https://example.com/download").unwrap();
+
+        assert!(check_content_type(&url, None).is_ok());
+        assert!(check_content_type(&url, Some("text/html; charset=utf-8")).is_ok());
+        assert!(check_content_type(&url, Some("application/rss+xml")).is_ok());
+        assert!(check_content_type(&url, Some("application/json")).is_ok());
+
+        assert_eq!(
+            check_content_type(&url, Some("Application/PDF")),
+            Err(BinaryContentType { url: url.clone(), content_type: "application/pdf".into() })
+        );
+        assert!(check_content_type(&url, Some("image/png")).is_err());
+        assert!(check_content_type(&url, Some("video/mp4")).is_err());
+        assert!(check_content_type(&url, Some("application/octet-stream")).is_ok());
+    }
+}
@@ -0,0 +1,138 @@
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::xml;
+
+/// The `<guid>` of a feed item
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Guid {
+    pub value: String,
+
+    /// Whether `value` is a url that can be opened in a browser.
+    ///
+    /// RSS readers assume this is true if it isn't specified, so we always emit it.
+    pub is_perma_link: bool,
+}
+
+/// How to generate the `<guid>` of each item in a feed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum GuidStrategy {
+    /// Use the items url
+    Url,
+
+    /// Use a hash of the items url and title.
+    ///
+    /// Useful when a site reuses the same url for multiple items (e.g. a "latest episode" link)
+    Hash,
+
+    /// Use the value of the named attribute on the item node (or its link node), e.g. `data-id`.
+    ///
+    /// Falls back to the items url if the attribute isn't present. Feed sources only support
+    /// `attribute:guid`, which keeps the upstream items guid.
+    Attribute(String),
+}
+
+impl Guid {
+    pub fn permalink(url: &Url) -> Guid {
+        Guid { value: url.to_string(), is_perma_link: true }
+    }
+
+    pub fn opaque<S: Into<String>>(value: S) -> Guid {
+        Guid { value: value.into(), is_perma_link: false }
+    }
+
+    pub fn to_rss_xml(&self) -> String {
+        format!("<guid isPermaLink=\"{}\">{}</guid>", self.is_perma_link, xml::escape(&self.value))
+    }
+}
+
+impl GuidStrategy {
+    /// The attribute to read from each item node, if this strategy needs one
+    pub fn attribute(&self) -> Option<&str> {
+        match self {
+            GuidStrategy::Attribute(attribute) => Some(attribute),
+            _ => None,
+        }
+    }
+
+    /// Generate a guid for an item. `attribute_value` is the value of `self.attribute()` on the item, if any.
+    pub fn guid_for(&self, title: &str, url: &Url, attribute_value: Option<&str>) -> Guid {
+        match self {
+            GuidStrategy::Url => Guid::permalink(url),
+            GuidStrategy::Hash => {
+                let mut hasher = Sha256::new();
+                hasher.update(url.as_str());
+                hasher.update("\n");
+                hasher.update(title);
+
+                Guid::opaque(format!("{:x}", hasher.finalize()))
+            },
+            GuidStrategy::Attribute(_) => attribute_value
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(Guid::opaque)
+                .unwrap_or_else(|| Guid::permalink(url)),
+        }
+    }
+}
+
+impl TryFrom<&str> for GuidStrategy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "url" => Ok(GuidStrategy::Url),
+            "hash" => Ok(GuidStrategy::Hash),
+            _ => match value.strip_prefix("attribute:") {
+                Some(attribute) if !attribute.is_empty() => Ok(GuidStrategy::Attribute(attribute.to_string())),
+                _ => Err(anyhow::anyhow!("{} is not a valid guid strategy (valid strategies are 'url', 'hash' and 'attribute:NAME')", value))
+            }
+        }
+    }
+}
+
+impl FromStr for GuidStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+impl fmt::Display for GuidStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuidStrategy::Url => write!(f, "url"),
+            GuidStrategy::Hash => write!(f, "hash"),
+            GuidStrategy::Attribute(attribute) => write!(f, "attribute:{}", attribute),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn guid_strategy_should_round_trip_through_strings() {
+        for strategy in &[GuidStrategy::Url, GuidStrategy::Hash, GuidStrategy::Attribute("data-id".into())] {
+            assert_eq!(strategy.to_string().parse::<GuidStrategy>().unwrap(), *strategy);
+        }
+
+        assert!("attribute:".parse::<GuidStrategy>().is_err());
+    }
+
+    #[test]
+    pub fn hash_guid_should_be_stable_and_not_a_permalink() {
+        let url = Url::parse("https://example.com/latest").unwrap();
+        let first = GuidStrategy::Hash.guid_for("Episode 1", &url, None);
+        let second = GuidStrategy::Hash.guid_for("Episode 2", &url, None);
+
+        assert_eq!(first, GuidStrategy::Hash.guid_for("Episode 1", &url, None));
+        assert_ne!(first, second);
+        assert!(!first.is_perma_link);
+    }
+}
@@ -0,0 +1,260 @@
+use ego_tree::NodeId;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use sxd_document::{dom, Package};
+use sxd_xpath::{nodeset, Context, Factory, Value, XPath};
+
+/// A selector targeting part of an HTML document.
+///
+/// Selectors are css selectors by default, or an XPath expression when prefixed with `xpath:`, e.g.
+/// `xpath://h3[contains(., 'Release')]/following-sibling::a[1]`
+#[derive(Debug, PartialEq)]
+pub enum HtmlSelector {
+    Css(Selector),
+    XPath(XPathSelector),
+}
+
+/// An XPath expression that has been checked to compile.
+///
+/// We keep the source rather than the compiled `XPath` since the latter can't be shared between threads.
+#[derive(Debug, PartialEq, Clone)]
+pub struct XPathSelector {
+    pub source: String,
+}
+
+impl HtmlSelector {
+    pub fn is_xpath(&self) -> bool {
+        matches!(self, HtmlSelector::XPath(_))
+    }
+}
+
+impl FromStr for HtmlSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(xpath) = value.strip_prefix("xpath:") {
+            XPathSelector::compile(xpath)?;
+            return Ok(HtmlSelector::XPath(XPathSelector { source: xpath.to_string() }));
+        }
+
+        let css = value.strip_prefix("css:").unwrap_or(value);
+        Selector::parse(css)
+            .map(HtmlSelector::Css)
+            .map_err(|e| anyhow::anyhow!("{:?}", e))
+    }
+}
+
+impl XPathSelector {
+    fn compile(source: &str) -> anyhow::Result<XPath> {
+        Factory::new()
+            .build(source)
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .ok_or_else(|| anyhow::anyhow!("'{}' is an empty XPath expression", source))
+    }
+}
+
+/// Runs `HtmlSelector`s of either kind against a parsed HTML document.
+///
+/// XPath can't run against scraper's tree directly, so when needed we mirror the document into an
+/// XML tree and map each XPath result back to the scraper node it came from.
+pub struct SelectorContext<'a> {
+    document: &'a Html,
+    mirror: Option<XPathMirror<'a>>,
+}
+
+struct XPathMirror<'d> {
+    root: dom::Root<'d>,
+    elements: HashMap<NodeId, dom::Element<'d>>,
+    node_ids: HashMap<dom::Element<'d>, NodeId>,
+
+    /// Each XPath selector compiled once per document, by source
+    compiled: RefCell<HashMap<String, XPath>>,
+}
+
+impl<'a> SelectorContext<'a> {
+    /// `package` holds the XPath mirror of `document` and is only used if `needs_xpath` is true.
+    pub fn new(document: &'a Html, package: &'a Package, needs_xpath: bool) -> SelectorContext<'a> {
+        let mirror = if needs_xpath { Some(XPathMirror::new(document, package)) } else { None };
+
+        SelectorContext { document, mirror }
+    }
+
+    /// Every node in the document matching `selector`
+    pub fn select_all(&self, selector: &HtmlSelector) -> Vec<ElementRef<'a>> {
+        match selector {
+            HtmlSelector::Css(css) => self.document.select(css).collect(),
+            HtmlSelector::XPath(xpath) => {
+                let mirror = self.mirror.as_ref().expect("XPath selectors need an XPath mirror");
+                self.resolve(mirror.select(xpath, mirror.root.into()))
+            }
+        }
+    }
+
+    /// The first node within `parent` matching `selector`
+    pub fn select_first(&self, selector: &HtmlSelector, parent: ElementRef<'a>) -> Option<ElementRef<'a>> {
+        match selector {
+            HtmlSelector::Css(css) => parent.select(css).next(),
+            HtmlSelector::XPath(xpath) => {
+                let mirror = self.mirror.as_ref().expect("XPath selectors need an XPath mirror");
+                let context_node = mirror.elements.get(&parent.id())?;
+                self.resolve(mirror.select(xpath, (*context_node).into())).into_iter().next()
+            }
+        }
+    }
+
+    /// Every node within `parent` matching `selector`
+    pub fn select_all_within(&self, selector: &HtmlSelector, parent: ElementRef<'a>) -> Vec<ElementRef<'a>> {
+        match selector {
+            HtmlSelector::Css(css) => parent.select(css).collect(),
+            HtmlSelector::XPath(xpath) => {
+                let mirror = self.mirror.as_ref().expect("XPath selectors need an XPath mirror");
+                match mirror.elements.get(&parent.id()) {
+                    Some(context_node) => self.resolve(mirror.select(xpath, (*context_node).into())),
+                    None => vec![],
+                }
+            }
+        }
+    }
+
+    fn resolve(&self, node_ids: Vec<NodeId>) -> Vec<ElementRef<'a>> {
+        node_ids
+            .into_iter()
+            .filter_map(|id| self.document.tree.get(id))
+            .filter_map(ElementRef::wrap)
+            .collect()
+    }
+}
+
+impl<'d> XPathMirror<'d> {
+    fn new(document: &Html, package: &'d Package) -> XPathMirror<'d> {
+        let xml_document = package.as_document();
+        let mut mirror = XPathMirror {
+            root: xml_document.root(),
+            elements: HashMap::new(),
+            node_ids: HashMap::new(),
+            compiled: RefCell::new(HashMap::new()),
+        };
+
+        for child in document.tree.root().children() {
+            // The root of an XML document can only contain elements, which is all we need from an HTML root.
+            if let Some(dom::ChildOfElement::Element(element)) = mirror.mirror_node(&xml_document, child) {
+                mirror.root.append_child(element);
+            }
+        }
+
+        mirror
+    }
+
+    fn mirror_node(
+        &mut self,
+        xml_document: &dom::Document<'d>,
+        node: ego_tree::NodeRef<Node>
+    ) -> Option<dom::ChildOfElement<'d>> {
+        match node.value() {
+            Node::Element(html_element) => {
+                let element = xml_document.create_element(html_element.name());
+                for (name, value) in html_element.attrs() {
+                    element.set_attribute_value(name, value);
+                }
+
+                self.node_ids.insert(element, node.id());
+                self.elements.insert(node.id(), element);
+
+                for child in node.children() {
+                    if let Some(child) = self.mirror_node(xml_document, child) {
+                        element.append_child(child);
+                    }
+                }
+
+                Some(element.into())
+            },
+            Node::Text(text) => Some(xml_document.create_text(text).into()),
+            _ => None,
+        }
+    }
+
+    fn select(&self, xpath: &XPathSelector, context_node: nodeset::Node<'d>) -> Vec<NodeId> {
+        let mut compiled = self.compiled.borrow_mut();
+        if !compiled.contains_key(&xpath.source) {
+            // We checked this compiles when the selector was built.
+            match XPathSelector::compile(&xpath.source) {
+                Ok(new) => compiled.insert(xpath.source.clone(), new),
+                Err(_) => return vec![],
+            };
+        }
+        let compiled = &compiled[&xpath.source];
+
+        let nodes = match compiled.evaluate(&Context::new(), context_node) {
+            Ok(Value::Nodeset(nodes)) => nodes.document_order(),
+            _ => return vec![],
+        };
+
+        nodes
+            .into_iter()
+            .filter_map(|node| match node {
+                nodeset::Node::Element(element) => Some(element),
+                nodeset::Node::Attribute(attribute) => attribute.parent(),
+                nodeset::Node::Text(text) => text.parent(),
+                _ => None,
+            })
+            .filter_map(|element| self.node_ids.get(&element).copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn xpath_selectors_should_support_structural_queries() {
+        let html = Html::parse_document(indoc! {r#"
+            <!DOCTYPE html>
+            <html>
+            <body>
+              <h3>News</h3>
+              <a href="/news">News link</a>
+              <h3>Release 1.2</h3>
+              <a href="/release-1.2">Release link</a>
+            </body>
+        "#});
+
+        let package = Package::new();
+        let context = SelectorContext::new(&html, &package, true);
+
+        let body: HtmlSelector = "body".parse().unwrap();
+        let link: HtmlSelector = "xpath:.//h3[contains(., 'Release')]/following-sibling::a[1]/@href".parse().unwrap();
+
+        let body = context.select_all(&body).into_iter().next().unwrap();
+        let link = context.select_first(&link, body).unwrap();
+
+        assert_eq!(link.value().attr("href"), Some("/release-1.2"));
+    }
+
+    #[test]
+    pub fn xpath_selectors_should_only_see_the_document_attributes() {
+        let html = Html::parse_document(r#"<html><body><p class="a">One</p><p>Two</p></body></html>"#);
+
+        let package = Package::new();
+        let context = SelectorContext::new(&html, &package, true);
+
+        let with_attributes: HtmlSelector = "xpath://p[@*]".parse().unwrap();
+        let paragraphs: HtmlSelector = "xpath://p".parse().unwrap();
+
+        assert_eq!(context.select_all(&with_attributes).len(), 1);
+        assert_eq!(context.select_all(&paragraphs).len(), 2);
+
+        // Selecting again reuses the compiled XPath
+        assert_eq!(context.select_all(&paragraphs).len(), 2);
+        assert_eq!(context.mirror.as_ref().unwrap().compiled.borrow().len(), 2);
+    }
+
+    #[test]
+    pub fn invalid_xpath_should_not_parse() {
+        assert!("xpath://h3[".parse::<HtmlSelector>().is_err());
+        assert!("xpath:".parse::<HtmlSelector>().is_err());
+    }
+}
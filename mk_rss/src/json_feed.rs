@@ -0,0 +1,101 @@
+use serde_json::{json, Map, Value};
+
+use super::feed::{Feed, FeedItem};
+
+impl Feed {
+    /// The same feed as `to_rss_xml`, as a JSON Feed 1.1 document
+    pub fn to_json_feed(&self) -> Value {
+        let mut feed = Map::new();
+        feed.insert("version".into(), json!("https://jsonfeed.org/version/1.1"));
+        feed.insert("title".into(), json!(self.name));
+        feed.insert("home_page_url".into(), json!(self.url.as_str()));
+
+        if let Some(self_url) = &self.self_url {
+            feed.insert("feed_url".into(), json!(self_url.as_str()));
+        }
+
+        if let Some(description) = &self.channel.description {
+            feed.insert("description".into(), json!(description));
+        }
+
+        if let Some(image) = &self.channel.image {
+            feed.insert("icon".into(), json!(image.as_str()));
+        }
+
+        if let Some(language) = &self.channel.language {
+            feed.insert("language".into(), json!(language));
+        }
+
+        feed.insert("items".into(), self.items.iter().map(FeedItem::to_json_feed).collect());
+
+        Value::Object(feed)
+    }
+}
+
+impl FeedItem {
+    pub fn to_json_feed(&self) -> Value {
+        let mut item = Map::new();
+        item.insert("id".into(), json!(self.guid.value));
+        item.insert("url".into(), json!(self.url.as_str()));
+        item.insert("title".into(), json!(self.title));
+        item.insert("date_published".into(), json!(self.pub_date.to_rfc3339()));
+
+        // JSON Feed requires some content, an empty text is how it says there isn't any.
+        match &self.description {
+            Some(description) => item.insert("content_html".into(), json!(description)),
+            None => item.insert("content_text".into(), json!("")),
+        };
+
+        if let Some(image) = &self.image {
+            item.insert("image".into(), json!(image.as_str()));
+        }
+
+        if let Some(author) = &self.author {
+            item.insert("authors".into(), json!([{ "name": author }]));
+        }
+
+        if !self.categories.is_empty() {
+            item.insert("tags".into(), json!(self.categories));
+        }
+
+        if let Some(enclosure) = &self.enclosure {
+            let mut attachment = json!({
+                "url": enclosure.url.as_str(),
+                "mime_type": enclosure.mime_type.as_deref().unwrap_or("application/octet-stream"),
+            });
+
+            if let Some(length) = enclosure.length {
+                attachment["size_in_bytes"] = json!(length);
+            }
+
+            item.insert("attachments".into(), json!([attachment]));
+        }
+
+        Value::Object(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::Enclosure;
+    use reqwest::Url;
+
+    #[test]
+    pub fn to_json_feed_should_describe_every_item() {
+        let item = FeedItem {
+            enclosure: Some(Enclosure::new(Url::parse("https://example.com/episode-1.mp3").unwrap())),
+            categories: vec!["podcast".into()],
+            ..FeedItem::for_test("Episode 1", "https://example.com/episode-1")
+        };
+        let feed = Feed::for_test("Example", vec![item]);
+
+        let json = feed.to_json_feed();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(json["items"][0]["id"], "https://example.com/episode-1");
+        assert_eq!(json["items"][0]["content_text"], "");
+        assert_eq!(json["items"][0]["tags"][0], "podcast");
+        assert_eq!(json["items"][0]["attachments"][0]["mime_type"], "audio/mpeg");
+        assert!(json.get("feed_url").is_none());
+    }
+}
@@ -2,18 +2,98 @@ mod feed_request;
 mod feed;
 mod website;
 mod fetch;
+mod rewrite;
+mod guid;
+mod xml;
+mod html_selector;
+mod structured_data;
+mod sitemap;
+mod upstream_feed;
+mod aggregate;
+mod discover;
+mod channel;
+mod media;
+mod seen;
+mod webhook;
+mod digest;
+mod paging;
+mod atom;
+mod json_feed;
+mod opml;
+mod site;
+mod token;
+mod url_policy;
+mod rate_limit;
+mod robots;
+mod charset;
+#[cfg(feature = "archive")]
+mod archive;
 
 pub use feed::{Feed, FeedItem};
-pub use feed_request::{FeedRequestBuilder, FeedRequest, FeedOrder};
-pub use website::{Website, WebsiteElement};
+pub use aggregate::{AggregateRequest, SourceTag, ItemSource};
+pub use channel::ChannelMetadata;
+pub use media::Enclosure;
+pub use paging::ArchiveLinks;
+pub use opml::{parse_opml, to_opml, OpmlOutline};
+pub use site::{BuildSummary, SiteEntry, StaticSite};
+pub use seen::SeenItems;
+pub use webhook::{Webhook, WebhookFormat};
+pub use digest::{Digest, DigestSection, SmtpConfig, SmtpTls};
+#[cfg(feature = "archive")]
+pub use archive::{Archive, ArchivedItem};
+pub use discover::{DiscoveredFeed, check_feed, discover_feeds};
+pub use feed_request::{definition_query_pairs, parse_feed_definitions, FeedRequestBuilder, FeedRequest, FeedOrder, FeedSource, HtmlSelectors, JsonPaths, SourceKind};
+pub use website::{parse_pub_date, Website, WebsiteElement};
+pub use rewrite::{RewriteRules, TitleRewrite, HostRewrite};
+pub use guid::{Guid, GuidStrategy};
+pub use url_policy::UrlPolicy;
+pub use rate_limit::HostLimits;
+pub use robots::RobotsDisallowed;
+pub use html_selector::{HtmlSelector, XPathSelector};
+pub use sitemap::{SitemapOptions, PathPattern};
+pub use fetch::{BinaryContentType, ResponseTooLarge};
 use fetch::fetch_url;
 use chrono::Local;
+use futures::future;
 
 pub async fn fetch_feed(request: FeedRequest) -> anyhow::Result<Feed> {
-    let body = fetch_url(request.url.clone()).await?;
-
     let now = Local::now();
-    let website = Website::scrape(&request, &body, now);
+
+    let mut website = match &request.source {
+        FeedSource::Sitemap(options) => sitemap::fetch_website(&request, options, now).await?,
+        _ => {
+            let body = fetch_url(request.url.clone(), &request.host_limits).await?.text(request.encoding);
+            Website::scrape(&request, &body, now)?
+        }
+    };
+
+    media::describe_enclosures(&mut website.elements, &request.host_limits).await;
+
     let feed = Feed::from_website(website, now);
     Ok(feed)
 }
+
+/// Fetch every feed in `request` concurrently and merge them into a single feed.
+///
+/// A feed that fails to fetch is left out rather than failing the whole aggregate, unless every feed fails.
+pub async fn fetch_aggregate_feed(request: AggregateRequest) -> anyhow::Result<Feed> {
+    let names: Vec<String> = request.feeds.iter().map(|feed| feed.name.clone()).collect();
+    let results = future::join_all(request.feeds.into_iter().map(fetch_feed)).await;
+
+    let mut feeds = vec![];
+    let mut last_error = None;
+    for (name, result) in names.into_iter().zip(results) {
+        match result {
+            Ok(feed) => feeds.push(feed),
+            Err(e) => {
+                log::warn!("Could not fetch {}: {}", name, e);
+                last_error = Some(e.context(format!("Could not fetch {}", name)));
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) if feeds.is_empty() => Err(e),
+        _ => Ok(Feed::merge(request.name, request.url, feeds, request.source_tag, request.max_items)),
+    }
+}
@@ -0,0 +1,257 @@
+use futures::future;
+use reqwest::Url;
+use scraper::ElementRef;
+
+use super::fetch::fetch_head;
+use super::rate_limit::HostLimits;
+use super::website::WebsiteElement;
+use super::xml;
+
+/// A media file attached to an item, e.g. a podcast episode
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Enclosure {
+    pub url: Url,
+
+    /// The MIME type of the file, e.g. `audio/mpeg`
+    pub mime_type: Option<String>,
+
+    /// The size of the file in bytes
+    pub length: Option<u64>,
+}
+
+impl Enclosure {
+    /// An enclosure we only know the url of, guessing its type from the file extension
+    pub fn new(url: Url) -> Enclosure {
+        let mime_type = guess_mime_type(&url).map(str::to_string);
+        Enclosure { url, mime_type, length: None }
+    }
+
+    /// The `<enclosure>` of this file and its `<media:content>` equivalent.
+    ///
+    /// RSS requires `<enclosure>` to have a type and length, so we fall back to
+    /// `application/octet-stream` and 0 (which readers treat as unknown).
+    pub fn to_rss_xml(&self) -> String {
+        let url = xml::escape(self.url.as_str());
+        let mime_type = xml::escape(self.mime_type.as_deref().unwrap_or("application/octet-stream"));
+
+        let medium = match self.mime_type.as_deref().and_then(|mime_type| mime_type.split('/').next()) {
+            Some(medium @ "audio") | Some(medium @ "video") | Some(medium @ "image") => format!(" medium=\"{}\"", medium),
+            _ => String::new(),
+        };
+
+        let file_size = match self.length {
+            Some(length) => format!(" fileSize=\"{}\"", length),
+            None => String::new(),
+        };
+
+        format!(
+            "<enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n<media:content url=\"{}\" type=\"{}\"{}{}/>",
+            url,
+            self.length.unwrap_or(0),
+            mime_type,
+            url,
+            mime_type,
+            medium,
+            file_size
+        )
+    }
+}
+
+pub fn thumbnail_xml(image: &Url) -> String {
+    format!("<media:thumbnail url=\"{}\"/>", xml::escape(image.as_str()))
+}
+
+/// Fill in the type and length of every enclosure in `elements` by asking the server hosting it.
+///
+/// Enclosures we already know the length of, e.g. from an upstream feed, are left alone.
+pub async fn describe_enclosures(elements: &mut [WebsiteElement], limits: &HostLimits) {
+    let enclosures = elements
+        .iter_mut()
+        .filter_map(|element| element.enclosure.as_mut())
+        .filter(|enclosure| enclosure.length.is_none());
+
+    future::join_all(enclosures.map(|enclosure| async move {
+        // Plenty of servers don't support HEAD requests, in which case we make do with our guess.
+        if let Ok(head) = fetch_head(enclosure.url.clone(), limits).await {
+            // Servers that don't know what a file is call it `application/octet-stream`, our guess is better than that.
+            if let Some(content_type) = head.content_type.filter(|content_type| content_type != "application/octet-stream") {
+                enclosure.mime_type = Some(content_type);
+            }
+
+            enclosure.length = head.content_length;
+        }
+    })).await;
+}
+
+/// The url of the image, audio or video in `element`.
+///
+/// Reads `srcset` (picking the largest candidate), `src` and lazy-loading variants, `href`,
+/// `content` and finally the first `<source>` within `element`.
+pub fn media_url(element: ElementRef) -> Option<String> {
+    let value = element.value();
+
+    value
+        .attr("srcset")
+        .or_else(|| value.attr("data-srcset"))
+        .and_then(largest_srcset_candidate)
+        .or_else(|| {
+            ["src", "data-src", "data-lazy-src", "href", "content", "poster"]
+                .iter()
+                .find_map(|attribute| value.attr(attribute))
+        })
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .or_else(|| {
+            element
+                .descendants()
+                .skip(1)
+                .filter_map(ElementRef::wrap)
+                .find(|child| matches!(child.value().name(), "source" | "img"))
+                .and_then(media_url)
+        })
+}
+
+/// The url of the largest image in a `srcset`, e.g. `small.jpg 480w, large.jpg 1080w` gives `large.jpg`
+fn largest_srcset_candidate(srcset: &str) -> Option<&str> {
+    srcset_candidates(srcset)
+        .into_iter()
+        .map(|(url, descriptor)| {
+            // Candidates without a descriptor are `1x`
+            let size = descriptor
+                .and_then(|descriptor| descriptor.trim_end_matches(&['w', 'x'][..]).parse::<f64>().ok())
+                .unwrap_or(1.0);
+
+            (url, size)
+        })
+        .fold(None, |largest: Option<(&str, f64)>, (url, size)| match largest {
+            Some((_, largest_size)) if largest_size >= size => largest,
+            _ => Some((url, size)),
+        })
+        .map(|(url, _)| url)
+}
+
+/// The url and descriptor of each candidate in a `srcset`.
+///
+/// Urls may contain commas (e.g. `/image/w_480,h_320/a.jpg`), so as the HTML spec parses it a comma
+/// only separates candidates after a descriptor or at the very end of a url.
+fn srcset_candidates(srcset: &str) -> Vec<(&str, Option<&str>)> {
+    let mut candidates = vec![];
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            return candidates;
+        }
+
+        let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (url, after_url) = rest.split_at(url_end);
+
+        if url.ends_with(',') {
+            candidates.push((url.trim_end_matches(','), None));
+            rest = after_url;
+            continue;
+        }
+
+        let descriptor_end = after_url.find(',').unwrap_or(after_url.len());
+        let descriptor = Some(after_url[..descriptor_end].trim()).filter(|descriptor| !descriptor.is_empty());
+        candidates.push((url, descriptor));
+        rest = &after_url[descriptor_end..];
+    }
+}
+
+/// The MIME type of common media files, based on the extension of `url`
+pub fn guess_mime_type(url: &Url) -> Option<&'static str> {
+    let (_, extension) = url.path().rsplit_once('.')?;
+
+    let mime_type = match extension.to_lowercase().as_str() {
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        _ => return None,
+    };
+
+    Some(mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scraper::{Html, Selector};
+
+    #[test]
+    pub fn media_url_should_prefer_the_largest_srcset_candidate() {
+        let document = Html::parse_fragment(r#"
+            <img class="srcset" src="/small.jpg" srcset="/small.jpg 480w, /large.jpg 1080w, /medium.jpg 800w">
+            <img class="lazy" data-src="/lazy.png">
+            <video class="video"><source src="/episode.mp4" type="video/mp4"></video>
+        "#);
+
+        let media_url_of = |selector: &str| {
+            let selector = Selector::parse(selector).unwrap();
+            media_url(document.select(&selector).next().unwrap())
+        };
+
+        assert_eq!(media_url_of(".srcset"), Some("/large.jpg".to_string()));
+        assert_eq!(media_url_of(".lazy"), Some("/lazy.png".to_string()));
+        assert_eq!(media_url_of(".video"), Some("/episode.mp4".to_string()));
+    }
+
+    #[test]
+    pub fn largest_srcset_candidate_should_keep_commas_in_urls() {
+        let srcset = "https://cdn.example.com/image/w_480,h_320/a.jpg 480w, https://cdn.example.com/image/w_1080,h_720/a.jpg 1080w";
+        assert_eq!(largest_srcset_candidate(srcset), Some("https://cdn.example.com/image/w_1080,h_720/a.jpg"));
+
+        assert_eq!(largest_srcset_candidate("/a.jpg, /b.jpg 2x"), Some("/b.jpg"));
+        assert_eq!(largest_srcset_candidate(" , "), None);
+    }
+
+    #[tokio::test]
+    pub async fn describe_enclosures_should_keep_what_it_cant_improve_on() {
+        let element = |enclosure: Enclosure| WebsiteElement {
+            title: "Episode 1".into(),
+            url: Url::parse("https://example.com/episode-1").unwrap(),
+            pub_date: None,
+            description: None,
+            guid: crate::guid::Guid::opaque("episode-1"),
+            image: None,
+            enclosure: Some(enclosure),
+            author: None,
+            categories: vec![],
+        };
+
+        // Private addresses are refused, so this one can't be described and keeps our guess
+        let unreachable = Enclosure::new(Url::parse("http://127.0.0.1/episode-1.mp3").unwrap());
+        let known = Enclosure { length: Some(1234), ..Enclosure::new(Url::parse("http://127.0.0.1/episode-1.ogg").unwrap()) };
+        let mut elements = vec![element(unreachable.clone()), element(known.clone())];
+
+        describe_enclosures(&mut elements, &HostLimits::default()).await;
+
+        assert_eq!(elements[0].enclosure, Some(unreachable));
+        assert_eq!(elements[1].enclosure, Some(known));
+    }
+
+    #[test]
+    pub fn enclosure_should_emit_rss_and_media_rss() {
+        let enclosure = Enclosure::new(Url::parse("https://example.com/episode-1.mp3").unwrap());
+
+        assert_eq!(enclosure.to_rss_xml(), concat!(
+            r#"<enclosure url="https://example.com/episode-1.mp3" length="0" type="audio/mpeg"/>"#,
+            "\n",
+            r#"<media:content url="https://example.com/episode-1.mp3" type="audio/mpeg" medium="audio"/>"#
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+use indoc::formatdoc;
+
+use super::xml;
+
+/// A feed listed in an OPML file
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct OpmlOutline {
+    pub title: String,
+
+    /// Where the feed itself is served from
+    pub xml_url: String,
+
+    /// The web page the feed is about
+    pub html_url: Option<String>,
+}
+
+impl OpmlOutline {
+    pub fn to_opml_xml(&self) -> String {
+        let html_url = match &self.html_url {
+            Some(html_url) => format!(" htmlUrl=\"{}\"", xml::escape(html_url)),
+            None => String::new(),
+        };
+
+        format!(
+            "<outline type=\"rss\" text=\"{}\" title=\"{}\" xmlUrl=\"{}\"{}/>",
+            xml::escape(&self.title),
+            xml::escape(&self.title),
+            xml::escape(&self.xml_url),
+            html_url
+        )
+    }
+}
+
+/// An OPML 2.0 subscription list named `title`, which most feed readers can import
+pub fn to_opml(title: &str, outlines: &[OpmlOutline]) -> String {
+    let outlines_xml = outlines
+        .iter()
+        .map(OpmlOutline::to_opml_xml)
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    formatdoc! {"
+        <?xml version=\"1.0\" encoding=\"UTF-8\"?>
+        <opml version=\"2.0\">
+        <head>
+        <title>{}</title>
+        </head>
+        <body>
+        {}
+        </body>
+        </opml>
+        ",
+        xml::escape(title),
+        outlines_xml
+    }
+}
+
+/// Every feed in an OPML subscription list, in order. Feeds nested in folders are included.
+pub fn parse_opml(text: &str) -> anyhow::Result<Vec<OpmlOutline>> {
+    let document = roxmltree::Document::parse(text)
+        .map_err(|e| anyhow::anyhow!("Could not parse OPML: {}", e))?;
+
+    if !document.root_element().has_tag_name("opml") {
+        return Err(anyhow::anyhow!("Expected an OPML file, found <{}>", document.root_element().tag_name().name()));
+    }
+
+    Ok(document
+        .descendants()
+        .filter(|node| node.has_tag_name("outline"))
+        .filter_map(|outline| {
+            // Outlines without an `xmlUrl` are folders
+            let xml_url = outline.attribute("xmlUrl")?.trim().to_string();
+            let title = outline
+                .attribute("title")
+                .or_else(|| outline.attribute("text"))
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .unwrap_or(&xml_url)
+                .to_string();
+
+            Some(OpmlOutline {
+                title,
+                html_url: outline.attribute("htmlUrl").map(str::trim).filter(|html_url| !html_url.is_empty()).map(str::to_string),
+                xml_url,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn to_opml_should_list_every_feed() {
+        let outlines = vec![OpmlOutline {
+            title: "Fish & Chips".into(),
+            xml_url: "https://feeds.example.com/fish-chips.xml".into(),
+            html_url: Some("https://example.com/".into()),
+        }];
+
+        let opml = to_opml("Feeds", &outlines);
+        let document = roxmltree::Document::parse(&opml).unwrap();
+        let outline = document.descendants().find(|node| node.has_tag_name("outline")).unwrap();
+
+        assert_eq!(outline.attribute("text"), Some("Fish & Chips"));
+        assert_eq!(outline.attribute("xmlUrl"), Some("https://feeds.example.com/fish-chips.xml"));
+        assert_eq!(outline.attribute("htmlUrl"), Some("https://example.com/"));
+        assert_eq!(parse_opml(&opml).unwrap(), outlines);
+    }
+
+    #[test]
+    pub fn parse_opml_should_read_nested_outlines() {
+        let outlines = parse_opml(indoc! {r#"
+            <?xml version="1.0"?>
+            <opml version="1.0">
+              <body>
+                <outline text="News">
+                  <outline text="Example News" type="rss" xmlUrl="https://example.com/news.xml"/>
+                </outline>
+                <outline type="rss" xmlUrl="https://example.com/blog.xml" htmlUrl=""/>
+              </body>
+            </opml>
+        "#}).unwrap();
+
+        assert_eq!(outlines, vec![
+            OpmlOutline { title: "Example News".into(), xml_url: "https://example.com/news.xml".into(), html_url: None },
+            OpmlOutline { title: "https://example.com/blog.xml".into(), xml_url: "https://example.com/blog.xml".into(), html_url: None },
+        ]);
+
+        assert!(parse_opml("<rss/>").is_err());
+    }
+}
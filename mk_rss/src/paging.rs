@@ -0,0 +1,68 @@
+use reqwest::Url;
+
+use super::xml;
+
+/// The `fh` namespace of RFC 5005, which marks a document as an archive
+pub const HISTORY_NAMESPACE: &str = "http://purl.org/syndication/history/1.0";
+
+/// The links between a feed and its archive documents, as described by RFC 5005.
+///
+/// Readers follow `prev_archive` from the current feed to backfill items that are no longer in it.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ArchiveLinks {
+    /// Whether this document is an archive, which never changes once published
+    pub is_archive: bool,
+
+    /// The current feed, only set on archive documents
+    pub current: Option<Url>,
+
+    /// The archive document before this one
+    pub prev_archive: Option<Url>,
+
+    /// The archive document after this one, only set on archive documents
+    pub next_archive: Option<Url>,
+}
+
+impl ArchiveLinks {
+    pub fn to_rss_xml(&self) -> String {
+        let links = [("current", &self.current), ("prev-archive", &self.prev_archive), ("next-archive", &self.next_archive)];
+
+        let mut elements: Vec<String> = links
+            .iter()
+            .filter_map(|(rel, url)| url.as_ref().map(|url| (rel, url)))
+            .map(|(rel, url)| format!("<atom:link href=\"{}\" rel=\"{}\" type=\"application/rss+xml\"/>", xml::escape(url.as_str()), rel))
+            .collect();
+
+        if self.is_archive {
+            elements.insert(0, "<fh:archive/>".to_string());
+        }
+
+        elements.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn to_rss_xml_should_mark_archives_and_link_pages() {
+        let url = |page: &str| Some(Url::parse(&format!("https://mk-rss.example.com/?name=Test{}", page)).unwrap());
+
+        let links = ArchiveLinks {
+            is_archive: true,
+            current: url(""),
+            prev_archive: url("&archive_page=1"),
+            next_archive: None,
+        };
+
+        assert_eq!(links.to_rss_xml(), concat!(
+            "<fh:archive/>\n",
+            r#"<atom:link href="https://mk-rss.example.com/?name=Test" rel="current" type="application/rss+xml"/>"#,
+            "\n",
+            r#"<atom:link href="https://mk-rss.example.com/?name=Test&amp;archive_page=1" rel="prev-archive" type="application/rss+xml"/>"#
+        ));
+
+        assert_eq!(ArchiveLinks::default().to_rss_xml(), "");
+    }
+}
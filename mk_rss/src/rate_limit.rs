@@ -0,0 +1,228 @@
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use super::robots;
+
+/// How many requests we make to a single host at once, unless a feed says otherwise
+const MAX_CONCURRENT_REQUESTS_VARIABLE: &str = "MK_RSS_HOST_MAX_CONCURRENT_REQUESTS";
+
+/// How many milliseconds we wait between starting requests to a single host, unless a feed says otherwise
+const REQUEST_INTERVAL_VARIABLE: &str = "MK_RSS_HOST_REQUEST_INTERVAL_MS";
+
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 2;
+const DEFAULT_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Every request this process makes goes through the same limiter, so feeds that share a site share its limits
+pub static HOST_LIMITER: Lazy<HostLimiter> = Lazy::new(HostLimiter::from_env);
+
+/// How politely a feed treats the hosts it fetches from. Anything not set uses the process wide default.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct HostLimits {
+    /// At most this many requests to the same host at once
+    pub max_concurrent_requests: Option<usize>,
+
+    /// Wait at least this long between starting requests to the same host
+    pub request_interval: Option<Duration>,
+
+    /// Whether to check robots.txt before fetching, and wait as long as its `Crawl-delay` asks
+    pub respect_robots_txt: Option<bool>,
+}
+
+impl HostLimits {
+    /// These limits, except that nothing may be looser than the process wide defaults. For feeds
+    /// defined by people we don't trust to decide how hard to hit someone else's site.
+    ///
+    /// They may turn checking robots.txt on, but not off when `MK_RSS_RESPECT_ROBOTS_TXT` turns it on.
+    pub fn no_looser_than_defaults(&self) -> HostLimits {
+        HostLimits {
+            respect_robots_txt: Some(self.respect_robots_txt == Some(true) || robots::respected_by_default()),
+            ..HOST_LIMITER.clamp(self)
+        }
+    }
+}
+
+/// Keeps track of the requests in flight to each host so we don't hammer small sites
+pub struct HostLimiter {
+    max_concurrent_requests: usize,
+    request_interval: Duration,
+    hosts: Mutex<HashMap<String, HostState>>,
+}
+
+#[derive(Default)]
+struct HostState {
+    active: usize,
+
+    /// The earliest the next request to this host may start
+    next_request: Option<Instant>,
+
+    /// Requests waiting for one in flight to finish
+    waiting: Vec<oneshot::Sender<()>>,
+}
+
+/// Permission to make a request to a host, which is given back when dropped
+pub struct HostPermit<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+}
+
+impl HostLimiter {
+    pub fn new(max_concurrent_requests: usize, request_interval: Duration) -> HostLimiter {
+        HostLimiter { max_concurrent_requests, request_interval, hosts: Mutex::new(HashMap::new()) }
+    }
+
+    /// A limiter with the defaults configured by `MK_RSS_HOST_MAX_CONCURRENT_REQUESTS` and `MK_RSS_HOST_REQUEST_INTERVAL_MS`
+    pub fn from_env() -> HostLimiter {
+        let max_concurrent_requests = env::var(MAX_CONCURRENT_REQUESTS_VARIABLE)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_REQUESTS);
+
+        let request_interval = env::var(REQUEST_INTERVAL_VARIABLE)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_REQUEST_INTERVAL);
+
+        HostLimiter::new(max_concurrent_requests, request_interval)
+    }
+
+    /// How long `limits` say to wait between requests to the same host
+    pub fn request_interval(&self, limits: &HostLimits) -> Duration {
+        limits.request_interval.unwrap_or(self.request_interval)
+    }
+
+    /// `limits` with anything looser than this limiter's defaults replaced by the default
+    pub fn clamp(&self, limits: &HostLimits) -> HostLimits {
+        let max_concurrent_requests = limits
+            .max_concurrent_requests
+            .map_or(self.max_concurrent_requests, |max_concurrent_requests| max_concurrent_requests.min(self.max_concurrent_requests));
+
+        HostLimits {
+            max_concurrent_requests: Some(max_concurrent_requests),
+            request_interval: Some(self.request_interval(limits).max(self.request_interval)),
+            ..*limits
+        }
+    }
+
+    /// Wait until `limits` allow another request to the host of `url`
+    pub async fn acquire(&self, url: &Url, limits: &HostLimits) -> HostPermit<'_> {
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        let max_concurrent_requests = limits.max_concurrent_requests.unwrap_or(self.max_concurrent_requests).max(1);
+        let request_interval = self.request_interval(limits);
+
+        loop {
+            let waiting = {
+                let mut hosts = self.hosts.lock().expect("no thread should panic while holding the lock");
+                let state = hosts.entry(host.clone()).or_default();
+
+                if state.active < max_concurrent_requests {
+                    let now = Instant::now();
+                    let start = state.next_request.map_or(now, |next_request| next_request.max(now));
+                    state.next_request = Some(start + request_interval);
+                    state.active += 1;
+                    Ok(start)
+                } else {
+                    let (sender, receiver) = oneshot::channel();
+                    state.waiting.push(sender);
+                    Err(receiver)
+                }
+            };
+
+            match waiting {
+                Ok(start) => {
+                    // Created before sleeping, so the slot is given back if we're cancelled while waiting
+                    let permit = HostPermit { limiter: self, host };
+                    tokio::time::sleep_until(start.into()).await;
+                    return permit;
+                },
+                // Another request finished, try again. An error just means the limiter was dropped.
+                Err(receiver) => { let _ = receiver.await; },
+            }
+        }
+    }
+
+    fn release(&self, host: &str) {
+        let mut hosts = self.hosts.lock().expect("no thread should panic while holding the lock");
+
+        if let Some(state) = hosts.get_mut(host) {
+            state.active -= 1;
+
+            for waiting in state.waiting.drain(..) {
+                let _ = waiting.send(());
+            }
+
+            // Forget hosts we're done with so a long running process doesn't remember every host it has ever seen
+            let is_waiting = matches!(state.next_request, Some(next_request) if next_request > Instant::now());
+            if state.active == 0 && !is_waiting {
+                hosts.remove(host);
+            }
+        }
+    }
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release(&self.host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn acquire_should_space_out_requests_to_the_same_host() {
+        let limiter = HostLimiter::new(4, Duration::from_millis(50));
+        let url = Url::parse("https://example.com/feed").unwrap();
+        let other_url = Url::parse("https://example.org/feed").unwrap();
+
+        let started = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire(&url, &HostLimits::default()).await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        let started = Instant::now();
+        let limits = HostLimits { request_interval: Some(Duration::from_secs(0)), ..HostLimits::default() };
+        limiter.acquire(&other_url, &limits).await;
+        limiter.acquire(&other_url, &limits).await;
+        assert!(started.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    pub fn clamp_should_only_make_limits_stricter() {
+        let limiter = HostLimiter::new(2, Duration::from_millis(250));
+
+        let loose = HostLimits { max_concurrent_requests: Some(100_000), request_interval: Some(Duration::from_secs(0)), respect_robots_txt: None };
+        assert_eq!(limiter.clamp(&loose), HostLimits {
+            max_concurrent_requests: Some(2),
+            request_interval: Some(Duration::from_millis(250)),
+            respect_robots_txt: None,
+        });
+
+        let strict = HostLimits { max_concurrent_requests: Some(1), request_interval: Some(Duration::from_secs(2)), respect_robots_txt: None };
+        assert_eq!(limiter.clamp(&strict), strict);
+    }
+
+    #[tokio::test]
+    pub async fn acquire_should_wait_for_a_request_to_finish() {
+        let limiter = HostLimiter::new(1, Duration::from_secs(0));
+        let url = Url::parse("https://example.com/feed").unwrap();
+
+        let permit = limiter.acquire(&url, &HostLimits::default()).await;
+        let waiting = tokio::time::timeout(Duration::from_millis(20), limiter.acquire(&url, &HostLimits::default())).await;
+        assert!(waiting.is_err());
+
+        let limits = HostLimits { max_concurrent_requests: Some(2), ..HostLimits::default() };
+        let second_permit = limiter.acquire(&url, &limits).await;
+
+        drop(permit);
+        drop(second_permit);
+        limiter.acquire(&url, &HostLimits::default()).await;
+    }
+}
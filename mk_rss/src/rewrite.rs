@@ -0,0 +1,175 @@
+use regex::Regex;
+use reqwest::Url;
+use std::str::FromStr;
+
+/// The separator between the two halves of a textual rewrite rule, e.g. `Read more »=>`
+const RULE_SEPARATOR: &str = "=>";
+
+/// Rules used to clean up the titles and urls of scraped items before they become part of a feed.
+#[derive(Debug, PartialEq, Default)]
+pub struct RewriteRules {
+    /// Regex search/replace rules applied, in order, to each items title.
+    pub title_rewrites: Vec<TitleRewrite>,
+
+    /// Query parameters to remove from each items url.
+    ///
+    /// A trailing `*` matches every parameter starting with the given prefix, e.g. `utm_*`
+    pub strip_query_params: Vec<String>,
+
+    /// Host rewrites applied to each items url, e.g. to point at a mobile or archive mirror.
+    pub host_rewrites: Vec<HostRewrite>,
+}
+
+/// Replace every match of `pattern` in a title with `replacement`.
+///
+/// `replacement` may refer to capture groups using `$1` or `${name}`.
+#[derive(Debug)]
+pub struct TitleRewrite {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+/// Replace the host `from` with `to` in an items url
+#[derive(Debug, PartialEq)]
+pub struct HostRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl RewriteRules {
+    pub fn rewrite_title(&self, title: &str) -> String {
+        let title = self.title_rewrites
+            .iter()
+            .fold(title.to_string(), |title, rewrite| {
+                rewrite.pattern.replace_all(&title, rewrite.replacement.as_str()).into_owned()
+            });
+
+        title.trim().to_string()
+    }
+
+    pub fn rewrite_url(&self, url: Url) -> Url {
+        let url = self.strip_query_params(url);
+        self.rewrite_host(url)
+    }
+
+    fn strip_query_params(&self, url: Url) -> Url {
+        if self.strip_query_params.is_empty() || url.query().is_none() {
+            return url;
+        }
+
+        let kept_pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| !self.should_strip_query_param(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+
+        let mut url = url;
+        if kept_pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut()
+               .clear()
+               .extend_pairs(kept_pairs);
+        }
+
+        url
+    }
+
+    fn should_strip_query_param(&self, name: &str) -> bool {
+        self.strip_query_params
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+    }
+
+    fn rewrite_host(&self, url: Url) -> Url {
+        let rewrite = self.host_rewrites
+            .iter()
+            .find(|rewrite| url.host_str() == Some(rewrite.from.as_str()));
+
+        let mut url = url;
+        if let Some(rewrite) = rewrite {
+            // `set_host` only fails for urls that can't have a host, which we've already matched on.
+            let _ = url.set_host(Some(&rewrite.to));
+        }
+
+        url
+    }
+}
+
+impl PartialEq for TitleRewrite {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() && self.replacement == other.replacement
+    }
+}
+
+/// Parses `PATTERN=>REPLACEMENT`
+impl FromStr for TitleRewrite {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (pattern, replacement) = split_rule(value)?;
+        let pattern = Regex::new(pattern)?;
+
+        Ok(TitleRewrite { pattern, replacement: replacement.to_string() })
+    }
+}
+
+/// Parses `FROM_HOST=>TO_HOST`
+impl FromStr for HostRewrite {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (from, to) = split_rule(value)?;
+        if from.is_empty() || to.is_empty() {
+            return Err(anyhow::anyhow!("{} must have a host on both sides of '{}'", value, RULE_SEPARATOR));
+        }
+
+        Ok(HostRewrite { from: from.to_string(), to: to.to_string() })
+    }
+}
+
+fn split_rule(value: &str) -> anyhow::Result<(&str, &str)> {
+    value
+        .split_once(RULE_SEPARATOR)
+        .ok_or_else(|| anyhow::anyhow!("{} is not a valid rule (expected 'FROM{}TO')", value, RULE_SEPARATOR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn rewrite_title_should_apply_each_rule_in_order() {
+        let rules = RewriteRules {
+            title_rewrites: vec![
+                "Read more »=>".parse().unwrap(),
+                r" \| Example News=>".parse().unwrap(),
+                r"^Episode (\d+)=>Ep. $1".parse().unwrap(),
+            ],
+            ..RewriteRules::default()
+        };
+
+        assert_eq!(
+            rules.rewrite_title("Episode 12: The Story | Example News Read more »"),
+            "Ep. 12: The Story"
+        );
+    }
+
+    #[test]
+    pub fn rewrite_url_should_strip_query_params_and_rewrite_host() {
+        let rules = RewriteRules {
+            strip_query_params: vec!["utm_*".into(), "sid".into()],
+            host_rewrites: vec!["example.com=>m.example.com".parse().unwrap()],
+            ..RewriteRules::default()
+        };
+
+        let url = Url::parse("https://example.com/story?id=1&utm_source=rss&utm_medium=feed&sid=abc").unwrap();
+        assert_eq!(rules.rewrite_url(url).as_str(), "https://m.example.com/story?id=1");
+
+        let url = Url::parse("https://example.com/story?utm_source=rss").unwrap();
+        assert_eq!(rules.rewrite_url(url).as_str(), "https://m.example.com/story");
+    }
+}
@@ -0,0 +1,240 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::rate_limit::HostLimits;
+
+/// Set to "true" to check robots.txt before fetching anything, unless a feed says otherwise
+const RESPECT_ROBOTS_TXT_VARIABLE: &str = "MK_RSS_RESPECT_ROBOTS_TXT";
+
+/// The name we look for in the `User-agent` lines of robots.txt, defaults to `mk-rss`
+const ROBOTS_USER_AGENT_VARIABLE: &str = "MK_RSS_ROBOTS_USER_AGENT";
+
+const DEFAULT_ROBOTS_USER_AGENT: &str = "mk-rss";
+
+/// How long we trust a robots.txt before fetching it again
+const CACHE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+static CACHE: Lazy<Mutex<HashMap<String, (Instant, RobotsTxt)>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The rules a site's robots.txt sets for us, see RFC 9309
+#[derive(Debug, Clone, Default)]
+pub struct RobotsTxt {
+    rules: Vec<RobotsRule>,
+
+    /// How long to wait between requests, a widely supported extension to RFC 9309
+    pub crawl_delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+struct RobotsRule {
+    allow: bool,
+
+    /// The pattern as written, the longest matching pattern wins
+    pattern: String,
+    regex: Regex,
+}
+
+/// The error returned when a site's robots.txt doesn't allow us to fetch a url
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RobotsDisallowed {
+    pub url: Url,
+}
+
+impl fmt::Display for RobotsDisallowed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is disallowed by robots.txt", self.url)
+    }
+}
+
+impl std::error::Error for RobotsDisallowed {}
+
+impl RobotsTxt {
+    /// Used when a site's robots.txt can't be reached, in which case we must assume we can't fetch anything
+    pub fn disallow_all() -> RobotsTxt {
+        RobotsTxt {
+            rules: vec![RobotsRule { allow: false, pattern: "/".into(), regex: pattern_regex("/") }],
+            crawl_delay: None,
+        }
+    }
+
+    /// The rules in `text` for `user_agent`, or for every robot (`*`) if none name it
+    pub fn parse(text: &str, user_agent: &str) -> RobotsTxt {
+        // Each group is the user agents it applies to followed by their rules
+        let mut groups: Vec<(Vec<String>, RobotsTxt)> = vec![];
+        let mut in_user_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let (key, value) = match line.find(':') {
+                Some(index) => (line[..index].trim().to_lowercase(), line[index + 1..].trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_user_agents {
+                        groups.push((vec![], RobotsTxt::default()));
+                        in_user_agents = true;
+                    }
+
+                    if let Some((user_agents, _)) = groups.last_mut() {
+                        user_agents.push(value.to_lowercase());
+                    }
+                },
+                "allow" | "disallow" | "crawl-delay" => {
+                    in_user_agents = false;
+
+                    // Rules before the first user agent don't belong to any group
+                    let rules = match groups.last_mut() {
+                        Some((_, rules)) => rules,
+                        None => continue,
+                    };
+
+                    if key == "crawl-delay" {
+                        rules.crawl_delay = value.parse::<f64>().ok().filter(|seconds| seconds.is_finite() && *seconds >= 0.0).map(Duration::from_secs_f64);
+                    } else if !value.is_empty() {
+                        rules.rules.push(RobotsRule { allow: key == "allow", pattern: value.to_string(), regex: pattern_regex(value) });
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let user_agent = user_agent.to_lowercase();
+        let applies_to = |name: &str| groups
+            .iter()
+            .filter(|(user_agents, _)| user_agents.iter().any(|user_agent| user_agent == name))
+            .map(|(_, rules)| rules.clone())
+            .collect::<Vec<RobotsTxt>>();
+
+        let mut matching = applies_to(&user_agent);
+        if matching.is_empty() {
+            matching = applies_to("*");
+        }
+
+        // A robot named by several groups follows all of them
+        matching.into_iter().fold(RobotsTxt::default(), |mut combined, group| {
+            combined.rules.extend(group.rules);
+            combined.crawl_delay = combined.crawl_delay.or(group.crawl_delay);
+            combined
+        })
+    }
+
+    /// Whether we may fetch `url`. The longest matching rule wins, and `Allow` wins a tie.
+    pub fn is_allowed(&self, url: &Url) -> bool {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| rule.regex.is_match(&path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+}
+
+/// Whether to check robots.txt before fetching, as `limits` or `MK_RSS_RESPECT_ROBOTS_TXT` say
+pub fn should_respect(limits: &HostLimits) -> bool {
+    limits.respect_robots_txt.unwrap_or_else(respected_by_default)
+}
+
+/// Whether `MK_RSS_RESPECT_ROBOTS_TXT` says to check robots.txt for feeds that don't say otherwise
+pub fn respected_by_default() -> bool {
+    env::var(RESPECT_ROBOTS_TXT_VARIABLE).map(|value| value == "true").unwrap_or(false)
+}
+
+/// The name robots.txt rules are looked up by
+pub fn user_agent() -> String {
+    env::var(ROBOTS_USER_AGENT_VARIABLE).unwrap_or_else(|_| DEFAULT_ROBOTS_USER_AGENT.to_string())
+}
+
+/// The robots.txt we fetched from `robots_url` in the last hour, if any
+pub fn cached(robots_url: &Url) -> Option<RobotsTxt> {
+    let cache = CACHE.lock().expect("no thread should panic while holding the lock");
+
+    cache
+        .get(robots_url.as_str())
+        .filter(|(fetched, _)| fetched.elapsed() < CACHE_DURATION)
+        .map(|(_, robots_txt)| robots_txt.clone())
+}
+
+pub fn cache(robots_url: &Url, robots_txt: &RobotsTxt) {
+    let mut cache = CACHE.lock().expect("no thread should panic while holding the lock");
+    cache.retain(|_, (fetched, _)| fetched.elapsed() < CACHE_DURATION);
+    cache.insert(robots_url.to_string(), (Instant::now(), robots_txt.clone()));
+}
+
+/// A regex for a robots.txt path pattern, where `*` matches anything and a trailing `$` anchors the end
+fn pattern_regex(pattern: &str) -> Regex {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, "$"),
+        None => (pattern, ""),
+    };
+
+    let pattern = regex::escape(pattern).replace(r"\*", ".*");
+    Regex::new(&format!("^{}{}", pattern, anchored)).expect("an escaped pattern should be a valid regex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    const ROBOTS_TXT: &str = indoc! {"
+        # Everyone else
+        User-agent: *
+        Disallow: /
+
+        User-agent: Googlebot
+        User-agent: mk-rss
+        Disallow: /private/
+        Allow: /private/news/
+        Disallow: /*.pdf$
+        Crawl-delay: 2.5
+    "};
+
+    fn is_allowed(robots_txt: &RobotsTxt, url: &str) -> bool {
+        robots_txt.is_allowed(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    pub fn parse_should_use_the_group_naming_us() {
+        let robots_txt = RobotsTxt::parse(ROBOTS_TXT, "MK-RSS");
+
+        assert_eq!(robots_txt.crawl_delay, Some(Duration::from_millis(2500)));
+        assert!(is_allowed(&robots_txt, "https://example.com/news"));
+        assert!(!is_allowed(&robots_txt, "https://example.com/private/feed"));
+        assert!(is_allowed(&robots_txt, "https://example.com/private/news/feed"));
+        assert!(!is_allowed(&robots_txt, "https://example.com/files/report.pdf"));
+        assert!(is_allowed(&robots_txt, "https://example.com/files/report.pdf?download=1"));
+
+        let robots_txt = RobotsTxt::parse(ROBOTS_TXT, "another-bot");
+        assert_eq!(robots_txt.crawl_delay, None);
+        assert!(!is_allowed(&robots_txt, "https://example.com/news"));
+        assert!(is_allowed(&robots_txt, "https://example.com/robots.txt"));
+    }
+
+    #[test]
+    pub fn parse_should_allow_everything_without_matching_rules() {
+        let robots_txt = RobotsTxt::parse("User-agent: other-bot\nDisallow: /\n\nUser-agent: *\nDisallow:\n", "mk-rss");
+        assert!(is_allowed(&robots_txt, "https://example.com/news"));
+
+        let robots_txt = RobotsTxt::parse("Disallow: /\n", "mk-rss");
+        assert!(is_allowed(&robots_txt, "https://example.com/news"));
+
+        assert!(!is_allowed(&RobotsTxt::disallow_all(), "https://example.com/news"));
+    }
+}
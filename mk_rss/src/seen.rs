@@ -0,0 +1,185 @@
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use super::feed::{Feed, FeedItem};
+
+/// We only need to remember guids that could still appear in a feed, so old ones are forgotten past this many.
+const MAX_GUIDS_PER_FEED: usize = 500;
+
+/// The guids of the items we've already seen in each feed, persisted between runs as JSON
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct SeenItems {
+    /// Feed key (see `feed_key`) to guids
+    feeds: HashMap<String, SeenGuids>,
+}
+
+/// The guids seen in one feed, with the order we saw them in so the oldest can be forgotten first
+#[derive(Debug, PartialEq, Eq, Default)]
+struct SeenGuids {
+    guids: HashSet<String>,
+
+    /// Oldest first
+    order: VecDeque<String>,
+}
+
+impl SeenGuids {
+    fn insert(&mut self, guid: &str) {
+        if self.guids.insert(guid.to_string()) {
+            self.order.push_back(guid.to_string());
+        }
+
+        while self.order.len() > MAX_GUIDS_PER_FEED {
+            if let Some(oldest) = self.order.pop_front() {
+                self.guids.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Identifies `feed` by its url as well as its name, so two feeds that happen to share a name don't share their seen items
+fn feed_key(feed: &Feed) -> String {
+    format!("{} {}", feed.url, feed.name)
+}
+
+impl SeenItems {
+    /// Load the seen items saved at `path`, or nothing if `path` doesn't exist yet
+    pub fn load(path: &Path) -> anyhow::Result<SeenItems> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(SeenItems::default()),
+            Err(e) => return Err(e).context(format!("failed to read {:?}", path)),
+        };
+
+        let value: Value = serde_json::from_str(&text)
+            .with_context(|| format!("Could not parse {:?} as seen items", path))?;
+
+        let feeds = value
+            .get("feeds")
+            .and_then(Value::as_object)
+            .map(|feeds| {
+                feeds
+                    .iter()
+                    .map(|(key, guids)| {
+                        let mut seen_guids = SeenGuids::default();
+                        for guid in guids.as_array().into_iter().flatten().filter_map(Value::as_str) {
+                            seen_guids.insert(guid);
+                        }
+
+                        (key.clone(), seen_guids)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(SeenItems { feeds })
+    }
+
+    /// Save to `path`, replacing it atomically so a crash can't leave us with half a file
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let feeds: Map<String, Value> = self.feeds
+            .iter()
+            .map(|(key, seen_guids)| (key.clone(), json!(seen_guids.order)))
+            .collect();
+
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, serde_json::to_string_pretty(&json!({ "feeds": feeds }))?)
+            .context(format!("failed to write {:?}", temporary_path))?;
+        fs::rename(&temporary_path, path).context(format!("failed to write {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Whether we've seen `feed` before. The first time we see a feed every item is new, which usually isn't what anyone wants to hear about.
+    pub fn knows(&self, feed: &Feed) -> bool {
+        self.feeds.contains_key(&feed_key(feed))
+    }
+
+    /// The items in `feed` we haven't seen, in feed order
+    pub fn new_items<'a>(&self, feed: &'a Feed) -> Vec<&'a FeedItem> {
+        let seen = self.feeds.get(&feed_key(feed));
+
+        feed.items
+            .iter()
+            .filter(|item| !seen.map(|seen| seen.guids.contains(&item.guid.value)).unwrap_or(false))
+            .collect()
+    }
+
+    /// Remember every item in `feed` as seen
+    pub fn mark_seen(&mut self, feed: &Feed) {
+        let seen = self.feeds.entry(feed_key(feed)).or_default();
+
+        // Feeds list the newest item first, we store the oldest first so forgetting old guids is a pop from the front.
+        for item in feed.items.iter().rev() {
+            seen.insert(&item.guid.value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Url;
+
+    fn feed(item_paths: &[&str]) -> Feed {
+        named_feed("Example", item_paths)
+    }
+
+    fn named_feed(name: &str, item_paths: &[&str]) -> Feed {
+        let items = item_paths
+            .iter()
+            .map(|path| FeedItem::for_test(path, &format!("https://example.com/{}", path)))
+            .collect();
+
+        Feed::for_test(name, items)
+    }
+
+    #[test]
+    pub fn new_items_should_only_include_unseen_guids_and_survive_a_save() {
+        let mut seen = SeenItems::default();
+        let first = feed(&["b", "a"]);
+        assert!(!seen.knows(&first));
+
+        seen.mark_seen(&first);
+
+        let second = feed(&["c", "b", "a"]);
+        let new_titles: Vec<&str> = seen.new_items(&second).iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(new_titles, vec!["c"]);
+
+        let path = std::env::temp_dir().join(format!("mk-rss-seen-test-{}.json", std::process::id()));
+        seen.save(&path).unwrap();
+        let loaded = SeenItems::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, seen);
+        assert!(loaded.knows(&second));
+    }
+
+    #[test]
+    pub fn feeds_should_be_told_apart_by_more_than_their_name() {
+        let mut seen = SeenItems::default();
+        seen.mark_seen(&feed(&["a"]));
+
+        let mut elsewhere = feed(&["a"]);
+        elsewhere.url = Url::parse("https://example.org/").unwrap();
+        assert!(!seen.knows(&elsewhere));
+        assert!(!seen.knows(&named_feed("Another", &["a"])));
+    }
+
+    #[test]
+    pub fn mark_seen_should_forget_the_oldest_guids() {
+        let mut seen = SeenItems::default();
+        let paths: Vec<String> = (0..MAX_GUIDS_PER_FEED + 10).map(|i| format!("item-{}", i)).collect();
+
+        // Feeds list the newest item first
+        let paths: Vec<&str> = paths.iter().rev().map(String::as_str).collect();
+        seen.mark_seen(&feed(&paths));
+
+        let later = feed(&["item-9", "item-10"]);
+        let new_titles: Vec<&str> = seen.new_items(&later).iter().map(|item| item.title.as_str()).collect();
+        assert_eq!(new_titles, vec!["item-9"]);
+    }
+}
@@ -0,0 +1,352 @@
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use indoc::formatdoc;
+use reqwest::Url;
+use roxmltree::Document;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::feed::Feed;
+use super::opml::{self, OpmlOutline};
+use super::xml;
+
+/// Writes feeds to a directory as static files, for hosting on plain object storage or a web server.
+///
+/// Every feed is written as RSS (`<slug>.xml`), Atom (`<slug>.atom`) and JSON Feed (`<slug>.json`),
+/// alongside an `index.html` listing them all and a `feeds.opml` for importing them into a reader.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StaticSite {
+    /// The title of the index page and OPML file
+    pub title: String,
+    pub output_dir: PathBuf,
+
+    /// The url `output_dir` is served from. Without it feeds can't link to themselves and the OPML file uses relative urls.
+    pub base_url: Option<Url>,
+}
+
+/// What a build changed
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct BuildSummary {
+    /// The files that were written because their content changed
+    pub written: Vec<PathBuf>,
+
+    /// How many files already had the right content
+    pub unchanged: usize,
+}
+
+/// A feed listed in the site's manifest
+#[derive(Debug)]
+pub struct SiteEntry {
+    pub name: String,
+    pub url: Url,
+
+    /// The feed, or `None` if it couldn't be fetched this time, in which case its files from the last build are kept
+    pub feed: Option<Feed>,
+}
+
+/// The dates in a feed we wrote earlier
+#[derive(Default)]
+struct PreviousBuild {
+    /// The publish date of each item by guid
+    pub_dates: HashMap<String, DateTime<Local>>,
+    last_build_date: Option<DateTime<Local>>,
+}
+
+/// A feed listed on the index page and in the OPML file, and the name of its files
+struct SiteFeed {
+    slug: String,
+    name: String,
+    url: Url,
+    description: Option<String>,
+}
+
+impl StaticSite {
+    pub fn new(title: &str, output_dir: &Path) -> StaticSite {
+        StaticSite { title: title.to_string(), output_dir: output_dir.to_path_buf(), base_url: None }
+    }
+
+    /// Write every file for `entries`, leaving alone any file whose content hasn't changed.
+    ///
+    /// Items already in a previously written feed keep their publish date, and each feed is only
+    /// as new as its newest item, so rebuilding a feed that hasn't changed writes nothing.
+    ///
+    /// Files are named after every entry in order, whether or not it could be fetched, so a feed
+    /// failing doesn't change the files of the feeds after it.
+    pub fn build(&self, entries: Vec<SiteEntry>) -> anyhow::Result<BuildSummary> {
+        fs::create_dir_all(&self.output_dir).context(format!("failed to create {:?}", self.output_dir))?;
+
+        let mut slugs = HashSet::new();
+        let mut summary = BuildSummary::default();
+        let mut site_feeds = vec![];
+
+        for SiteEntry { name, url, feed } in entries {
+            let slug = unique_slug(&name, &mut slugs);
+
+            let mut feed = match feed {
+                Some(feed) => self.stabilize_dates(feed, &slug)?,
+                // Still listed as long as it has files from an earlier build
+                None if self.output_dir.join(format!("{}.xml", slug)).exists() => {
+                    site_feeds.push(SiteFeed { slug, name, url, description: None });
+                    continue;
+                },
+                None => continue,
+            };
+
+            let rss = self.render(&mut feed, &slug, "xml", Feed::to_rss_xml);
+            self.write(&mut summary, &format!("{}.xml", slug), &rss)?;
+
+            let atom = self.render(&mut feed, &slug, "atom", Feed::to_atom_xml);
+            self.write(&mut summary, &format!("{}.atom", slug), &atom)?;
+
+            let json = self.render(&mut feed, &slug, "json", |feed| feed.to_json_feed().to_string());
+            self.write(&mut summary, &format!("{}.json", slug), &json)?;
+
+            site_feeds.push(SiteFeed { slug, name: feed.name, url: feed.url, description: feed.channel.description });
+        }
+
+        self.write(&mut summary, "index.html", &self.index_html(&site_feeds))?;
+        self.write(&mut summary, "feeds.opml", &opml::to_opml(&self.title, &self.opml_outlines(&site_feeds)))?;
+
+        Ok(summary)
+    }
+
+    /// Render `feed` with `to_text`, linking it to the file named `slug` with `extension` it's served from
+    fn render(&self, feed: &mut Feed, slug: &str, extension: &str, to_text: impl Fn(&Feed) -> String) -> String {
+        feed.self_url = self.file_url(&format!("{}.{}", slug, extension));
+        to_text(feed)
+    }
+
+    /// Keep the dates of the last build where we can, so that an unchanged feed renders identically
+    fn stabilize_dates(&self, mut feed: Feed, slug: &str) -> anyhow::Result<Feed> {
+        let path = self.output_dir.join(format!("{}.xml", slug));
+        let previous = match fs::read_to_string(&path) {
+            Ok(previous) => previous,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context(format!("failed to read {:?}", path)),
+        };
+
+        // A file we can't parse is simply replaced
+        let previous = previous_build(&previous).unwrap_or_default();
+
+        for item in feed.items.iter_mut() {
+            if let Some(pub_date) = previous.pub_dates.get(&item.guid.value) {
+                item.pub_date = *pub_date;
+            }
+        }
+
+        feed.last_build_date = feed.items
+            .iter()
+            .map(|item| item.pub_date)
+            .max()
+            .or(previous.last_build_date)
+            .unwrap_or(feed.last_build_date);
+
+        Ok(feed)
+    }
+
+    fn index_html(&self, site_feeds: &[SiteFeed]) -> String {
+        let feeds_html = site_feeds
+            .iter()
+            .map(|SiteFeed { slug, name, url, description }| {
+                let description = match description {
+                    Some(description) => format!("<p>{}</p>\n", xml::escape(description)),
+                    None => String::new(),
+                };
+
+                formatdoc! {"
+                    <li>
+                    <h2><a href=\"{}\">{}</a></h2>
+                    {}<p><a href=\"{}.xml\">RSS</a> · <a href=\"{}.atom\">Atom</a> · <a href=\"{}.json\">JSON Feed</a></p>
+                    </li>",
+                    xml::escape(url.as_str()),
+                    xml::escape(name),
+                    description,
+                    slug,
+                    slug,
+                    slug
+                }
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let alternate_links = site_feeds
+            .iter()
+            .map(|SiteFeed { slug, name, .. }| format!(
+                "<link rel=\"alternate\" type=\"application/rss+xml\" title=\"{}\" href=\"{}.xml\">",
+                xml::escape(name),
+                slug
+            ))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        formatdoc! {"
+            <!DOCTYPE html>
+            <html>
+            <head>
+            <meta charset=\"utf-8\">
+            <title>{}</title>
+            {}
+            </head>
+            <body>
+            <h1>{}</h1>
+            <p><a href=\"feeds.opml\">Subscribe to every feed (OPML)</a></p>
+            <ul>
+            {}
+            </ul>
+            </body>
+            </html>
+            ",
+            xml::escape(&self.title),
+            alternate_links,
+            xml::escape(&self.title),
+            feeds_html
+        }
+    }
+
+    fn opml_outlines(&self, site_feeds: &[SiteFeed]) -> Vec<OpmlOutline> {
+        site_feeds
+            .iter()
+            .map(|SiteFeed { slug, name, url, .. }| {
+                let file_name = format!("{}.xml", slug);
+
+                OpmlOutline {
+                    title: name.clone(),
+                    xml_url: self.file_url(&file_name).map(|url| url.to_string()).unwrap_or(file_name),
+                    html_url: Some(url.to_string()),
+                }
+            })
+            .collect()
+    }
+
+    fn file_url(&self, file_name: &str) -> Option<Url> {
+        self.base_url.as_ref().and_then(|base_url| base_url.join(file_name).ok())
+    }
+
+    /// Write `contents` to `file_name` unless it already has them, replacing it atomically so readers never see half a file
+    fn write(&self, summary: &mut BuildSummary, file_name: &str, contents: &str) -> anyhow::Result<()> {
+        let path = self.output_dir.join(file_name);
+
+        if fs::read_to_string(&path).map(|existing| existing == contents).unwrap_or(false) {
+            summary.unchanged += 1;
+            return Ok(());
+        }
+
+        let temporary_path = self.output_dir.join(format!(".{}.tmp", file_name));
+        fs::write(&temporary_path, contents).context(format!("failed to write {:?}", temporary_path))?;
+        fs::rename(&temporary_path, &path).context(format!("failed to write {:?}", path))?;
+
+        summary.written.push(path);
+        Ok(())
+    }
+}
+
+fn previous_build(rss: &str) -> Option<PreviousBuild> {
+    let document = Document::parse(rss).ok()?;
+    let parse_date = |text: Option<&str>| {
+        text.and_then(|text| DateTime::parse_from_rfc2822(text).ok()).map(|date| date.with_timezone(&Local))
+    };
+
+    let pub_dates = document
+        .descendants()
+        .filter(|node| node.has_tag_name("item"))
+        .filter_map(|item| Some((child_text(item, "guid")?.to_string(), parse_date(child_text(item, "pubDate"))?)))
+        .collect();
+
+    let last_build_date = document
+        .descendants()
+        .find(|node| node.has_tag_name("channel"))
+        .and_then(|channel| parse_date(child_text(channel, "lastBuildDate")));
+
+    Some(PreviousBuild { pub_dates, last_build_date })
+}
+
+/// The text of the first child of `node` named `name`
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children().find(|child| child.has_tag_name(name)).and_then(|child| child.text())
+}
+
+/// A file name for `name` that no other feed in `slugs` has, e.g. `Fish & Chips` becomes `fish-chips`
+fn unique_slug(name: &str, slugs: &mut HashSet<String>) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+
+    let slug = if slug.is_empty() { "feed".to_string() } else { slug };
+
+    let mut unique_slug = slug.clone();
+    let mut suffix = 2;
+    while !slugs.insert(unique_slug.clone()) {
+        unique_slug = format!("{}-{}", slug, suffix);
+        suffix += 1;
+    }
+
+    unique_slug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feed::FeedItem;
+    use chrono::TimeZone;
+
+    fn feed(name: &str, now: DateTime<Local>) -> Feed {
+        // Like an item whose date was inferred from when it was fetched
+        let item = FeedItem { pub_date: now, ..FeedItem::for_test("The Story", "https://example.com/the-story") };
+        Feed { last_build_date: now, ..Feed::for_test(name, vec![item]) }
+    }
+
+    fn entry(feed: Feed) -> SiteEntry {
+        SiteEntry { name: feed.name.clone(), url: feed.url.clone(), feed: Some(feed) }
+    }
+
+    #[test]
+    pub fn build_should_only_rewrite_changed_files() {
+        let output_dir = std::env::temp_dir().join(format!("mk-rss-site-test-{}", std::process::id()));
+        let mut site = StaticSite::new("Feeds", &output_dir);
+        site.base_url = Some(Url::parse("https://feeds.example.com/").unwrap());
+
+        let first_build = Local.ymd(2021, 1, 10).and_hms(12, 0, 0);
+        let summary = site.build(vec![entry(feed("Fish & Chips", first_build)), entry(feed("Fish & Chips", first_build))]).unwrap();
+        assert_eq!(summary.written.len(), 8);
+        assert!(output_dir.join("fish-chips-2.atom").exists());
+
+        let rebuild = Local.ymd(2021, 1, 11).and_hms(12, 0, 0);
+        let summary = site.build(vec![entry(feed("Fish & Chips", rebuild)), entry(feed("Fish & Chips", rebuild))]).unwrap();
+        assert_eq!(summary, BuildSummary { written: vec![], unchanged: 8 });
+
+        let rss = fs::read_to_string(output_dir.join("fish-chips.xml")).unwrap();
+        let opml = fs::read_to_string(output_dir.join("feeds.opml")).unwrap();
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        assert!(rss.contains(&first_build.to_rfc2822()));
+        assert!(rss.contains("https://feeds.example.com/fish-chips.xml"));
+        assert!(opml.contains("xmlUrl=\"https://feeds.example.com/fish-chips-2.xml\""));
+    }
+
+    #[test]
+    pub fn build_should_keep_failed_feeds() {
+        let output_dir = std::env::temp_dir().join(format!("mk-rss-site-failed-test-{}", std::process::id()));
+        let site = StaticSite::new("Feeds", &output_dir);
+        let now = Local.ymd(2021, 1, 10).and_hms(12, 0, 0);
+
+        site.build(vec![entry(feed("Fish & Chips", now)), entry(feed("Fish & Chips", now))]).unwrap();
+
+        let failed = SiteEntry { name: "Fish & Chips".into(), url: Url::parse("https://example.com/").unwrap(), feed: None };
+        let never_built = SiteEntry { name: "Burgers".into(), url: Url::parse("https://example.com/").unwrap(), feed: None };
+        let summary = site.build(vec![failed, entry(feed("Fish & Chips", now)), never_built]).unwrap();
+
+        let index = fs::read_to_string(output_dir.join("index.html")).unwrap();
+        let opml = fs::read_to_string(output_dir.join("feeds.opml")).unwrap();
+        fs::remove_dir_all(&output_dir).unwrap();
+
+        assert_eq!(summary, BuildSummary { written: vec![], unchanged: 5 });
+        assert!(index.contains("href=\"fish-chips.xml\""));
+        assert!(!index.contains("burgers"));
+        assert!(opml.contains("xmlUrl=\"fish-chips-2.xml\""));
+    }
+}
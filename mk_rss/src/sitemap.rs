@@ -0,0 +1,279 @@
+use chrono::{DateTime, Local};
+use futures::future;
+use regex::Regex;
+use reqwest::Url;
+use scraper::Html;
+use std::collections::HashSet;
+
+use super::channel;
+use super::feed_request::FeedRequest;
+use super::fetch::fetch_url;
+use super::rate_limit::HostLimits;
+use super::website::{self, Website};
+
+/// Sitemap indexes can point at thousands of sitemaps, we only follow this many.
+const MAX_SITEMAPS: usize = 20;
+
+#[derive(Debug, PartialEq)]
+pub struct SitemapOptions {
+    /// Only urls whose path matches this pattern become items
+    pub path_pattern: Option<PathPattern>,
+
+    /// Whether to fetch each page to find its title. Otherwise we guess the title from the url.
+    pub fetch_titles: bool,
+}
+
+/// A regex matched against the path of each url in a sitemap
+#[derive(Debug)]
+pub struct PathPattern(pub Regex);
+
+impl PartialEq for PathPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SitemapEntry {
+    pub url: Url,
+    pub lastmod: Option<DateTime<Local>>,
+    pub title: Option<String>,
+}
+
+/// The contents of a single sitemap document
+#[derive(Debug, PartialEq, Default)]
+pub struct Sitemap {
+    pub entries: Vec<SitemapEntry>,
+
+    /// The sitemaps listed by a sitemap index
+    pub sitemaps: Vec<Url>,
+}
+
+/// Build a `Website` from the sitemap (or sitemap index) at `request.url`
+pub async fn fetch_website(request: &FeedRequest, options: &SitemapOptions, now: DateTime<Local>) -> anyhow::Result<Website> {
+    let entries = fetch_entries(request.url.clone(), &request.host_limits, now).await?;
+    let mut entries = select_entries(entries, options, request.max_items);
+
+    if options.fetch_titles {
+        let titles = future::join_all(entries.iter().map(|entry| fetch_page_title(entry.url.clone(), request))).await;
+        for (entry, title) in entries.iter_mut().zip(titles) {
+            entry.title = title;
+        }
+    }
+
+    Ok(Website::from_sitemap_entries(request, entries, now))
+}
+
+/// Every entry in the sitemap at `url`, following sitemap indexes.
+///
+/// Only a failure to read the sitemap at `url` itself is an error. A broken sitemap listed in an
+/// index is skipped, as are gzipped ones, which we can't read.
+async fn fetch_entries(url: Url, limits: &HostLimits, now: DateTime<Local>) -> anyhow::Result<Vec<SitemapEntry>> {
+    let root = url.clone();
+    let mut pending = vec![url];
+    let mut visited = HashSet::new();
+    let mut entries = vec![];
+
+    while let Some(url) = pending.pop() {
+        if visited.len() >= MAX_SITEMAPS || !visited.insert(url.clone()) {
+            continue;
+        }
+
+        if url != root && url.path().ends_with(".gz") {
+            log::info!("Skipping {}, gzipped sitemaps aren't supported", url);
+            continue;
+        }
+
+        let sitemap = match fetch_sitemap(&url, limits, now).await {
+            Ok(sitemap) => sitemap,
+            Err(e) if url == root => return Err(e),
+            Err(e) => {
+                log::warn!("Skipping sitemap {}: {}", url, e);
+                continue;
+            },
+        };
+
+        entries.extend(sitemap.entries);
+        pending.extend(sitemap.sitemaps.into_iter().rev());
+    }
+
+    Ok(entries)
+}
+
+async fn fetch_sitemap(url: &Url, limits: &HostLimits, now: DateTime<Local>) -> anyhow::Result<Sitemap> {
+    let body = fetch_url(url.clone(), limits).await?.text(None);
+    parse(&body, url, now)
+}
+
+/// Parse a `<urlset>` or `<sitemapindex>` document. Relative urls are resolved against `base_url`.
+pub fn parse(body: &str, base_url: &Url, now: DateTime<Local>) -> anyhow::Result<Sitemap> {
+    let document = roxmltree::Document::parse(body)
+        .map_err(|e| anyhow::anyhow!("Could not parse {} as a sitemap: {}", base_url, e))?;
+
+    let root = document.root_element();
+    let child_text = |node: roxmltree::Node, name: &str| {
+        node.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(|text| text.trim().to_string())
+    };
+
+    let locations = |name: &str| {
+        root.children()
+            .filter(|node| node.tag_name().name() == name)
+            .filter_map(|node| {
+                let url = base_url.join(&child_text(node, "loc")?).ok()?;
+                let lastmod = child_text(node, "lastmod").and_then(|text| website::parse_pub_date(&text, now));
+                Some((url, lastmod))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    match root.tag_name().name() {
+        "urlset" => Ok(Sitemap {
+            entries: locations("url")
+                .into_iter()
+                .map(|(url, lastmod)| SitemapEntry { url, lastmod, title: None })
+                .collect(),
+            sitemaps: vec![],
+        }),
+        "sitemapindex" => Ok(Sitemap {
+            entries: vec![],
+            sitemaps: locations("sitemap").into_iter().map(|(url, _)| url).collect(),
+        }),
+        name => Err(anyhow::anyhow!("{} is not a sitemap (expected <urlset> or <sitemapindex>, found <{}>)", base_url, name)),
+    }
+}
+
+/// The entries that should become items: those matching `options.path_pattern`, most recently modified first
+pub fn select_entries(entries: Vec<SitemapEntry>, options: &SitemapOptions, max_items: usize) -> Vec<SitemapEntry> {
+    let mut entries: Vec<SitemapEntry> = entries
+        .into_iter()
+        .filter(|entry| match &options.path_pattern {
+            Some(PathPattern(pattern)) => pattern.is_match(entry.url.path()),
+            None => true,
+        })
+        .collect();
+
+    // `None` sorts before `Some`, so reversing puts undated entries last
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.lastmod));
+    entries.truncate(max_items);
+    entries
+}
+
+/// The title of the page at `url`, which is on the same site as the sitemap `request` is for
+async fn fetch_page_title(url: Url, request: &FeedRequest) -> Option<String> {
+    let body = fetch_url(url, &request.host_limits).await.ok()?.text(request.encoding);
+    channel::page_title(&Html::parse_document(&body))
+}
+
+/// Guess a title from the last segment of a urls path, e.g. `/blog/my-first-post.html` becomes `my first post`
+pub fn title_from_url(url: &Url) -> String {
+    let segment = url
+        .path_segments()
+        .and_then(|segments| segments.rev().find(|segment| !segment.is_empty()))
+        .unwrap_or_default();
+
+    let segment = segment.rsplit_once('.').map(|(name, _)| name).unwrap_or(segment);
+    let title = segment.replace(&['-', '_'][..], " ");
+
+    if title.trim().is_empty() {
+        url.to_string()
+    } else {
+        title.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use indoc::indoc;
+
+    #[tokio::test]
+    pub async fn fetch_entries_should_skip_sitemaps_it_cannot_read() {
+        use crate::fetch::{cache_for_test, Page};
+
+        let page = |body: &str| Page { body: body.as_bytes().to_vec(), content_type: Some("application/xml".into()) };
+        let index = Url::parse("https://sitemap-index.example.com/sitemap.xml").unwrap();
+
+        cache_for_test(&index, &page(indoc! {r#"
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>/broken.xml</loc></sitemap>
+              <sitemap><loc>/compressed.xml.gz</loc></sitemap>
+              <sitemap><loc>/posts.xml</loc></sitemap>
+            </sitemapindex>
+        "#}));
+        cache_for_test(&index.join("/broken.xml").unwrap(), &page("<html>Not found</html"));
+        cache_for_test(&index.join("/posts.xml").unwrap(), &page(indoc! {r#"
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>/posts/1</loc></url>
+            </urlset>
+        "#}));
+
+        let limits = HostLimits { respect_robots_txt: Some(false), ..HostLimits::default() };
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let entries = fetch_entries(index.clone(), &limits, now).await.unwrap();
+        let urls: Vec<&str> = entries.iter().map(|entry| entry.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://sitemap-index.example.com/posts/1"]);
+
+        let broken = index.join("/broken.xml").unwrap();
+        assert!(fetch_entries(broken, &limits, now).await.is_err());
+    }
+
+    #[test]
+    pub fn parse_urlset_and_sitemap_index() {
+        let base_url = Url::parse("https://example.com/sitemap.xml").unwrap();
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+
+        let urlset = parse(indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://example.com/blog/first-post</loc><lastmod>2021-01-10</lastmod></url>
+              <url><loc>https://example.com/about</loc></url>
+            </urlset>
+        "#}, &base_url, now).unwrap();
+
+        assert_eq!(urlset.entries.len(), 2);
+        assert_eq!(urlset.entries[0].lastmod, Some(Local.ymd(2021, 1, 10).and_hms(0, 0, 0)));
+        assert_eq!(urlset.entries[1].lastmod, None);
+
+        let index = parse(indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <sitemap><loc>https://example.com/sitemap-posts.xml</loc></sitemap>
+            </sitemapindex>
+        "#}, &base_url, now).unwrap();
+
+        assert_eq!(index.sitemaps, vec![Url::parse("https://example.com/sitemap-posts.xml").unwrap()]);
+    }
+
+    #[test]
+    pub fn select_entries_should_filter_by_path_and_sort_by_lastmod() {
+        let entry = |path: &str, day: Option<u32>| SitemapEntry {
+            url: Url::parse("https://example.com/").unwrap().join(path).unwrap(),
+            lastmod: day.map(|day| Local.ymd(2021, 1, day).and_hms(0, 0, 0)),
+            title: None,
+        };
+
+        let options = SitemapOptions {
+            path_pattern: Some(PathPattern(Regex::new("^/blog/").unwrap())),
+            fetch_titles: false,
+        };
+
+        let entries = vec![
+            entry("/blog/undated", None),
+            entry("/blog/older", Some(1)),
+            entry("/about", Some(20)),
+            entry("/blog/newer", Some(10)),
+        ];
+
+        let paths: Vec<String> = select_entries(entries, &options, 30)
+            .into_iter()
+            .map(|entry| entry.url.path().to_string())
+            .collect();
+
+        assert_eq!(paths, vec!["/blog/newer", "/blog/older", "/blog/undated"]);
+        assert_eq!(title_from_url(&Url::parse("https://example.com/blog/my-first_post.html").unwrap()), "my first post");
+    }
+}
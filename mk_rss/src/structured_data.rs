@@ -0,0 +1,269 @@
+use scraper::{ElementRef, Html, Selector};
+use serde_json::Value;
+
+/// The schema.org types we treat as feed items
+const ITEM_TYPES: &[&str] = &[
+    "Article",
+    "BlogPosting",
+    "NewsArticle",
+    "TechArticle",
+    "ScholarlyArticle",
+    "Report",
+    "SocialMediaPosting",
+    "LiveBlogPosting",
+    "PodcastEpisode",
+    "VideoObject",
+];
+
+/// An item described by the schema.org structured data (JSON-LD or microdata) embedded in a page.
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct StructuredItem {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub pub_date: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+}
+
+/// Find every item described by the JSON-LD and microdata in `document`, in document order.
+pub fn extract_items(document: &Html) -> Vec<StructuredItem> {
+    let mut items = extract_json_ld_items(document);
+    items.extend(extract_microdata_items(document));
+    items
+}
+
+fn extract_json_ld_items(document: &Html) -> Vec<StructuredItem> {
+    let selector = Selector::parse(r#"script[type="application/ld+json"]"#).expect("selector should be valid");
+    let mut items = vec![];
+
+    for script in document.select(&selector) {
+        // Plenty of sites ship broken JSON-LD, there's nothing useful we can do with it.
+        if let Ok(value) = serde_json::from_str::<Value>(&script.text().collect::<String>()) {
+            collect_json_ld_items(&value, &mut items);
+        }
+    }
+
+    items
+}
+
+fn collect_json_ld_items(value: &Value, items: &mut Vec<StructuredItem>) {
+    match value {
+        Value::Array(values) => values.iter().for_each(|value| collect_json_ld_items(value, items)),
+        Value::Object(object) => {
+            if let Some(graph) = object.get("@graph") {
+                collect_json_ld_items(graph, items);
+            }
+
+            if has_type(value, &["ItemList"]) {
+                for list_item in json_array(value.get("itemListElement")) {
+                    match list_item.get("item") {
+                        Some(item @ Value::Object(_)) => collect_json_ld_items(item, items),
+                        Some(Value::String(url)) => items.push(StructuredItem {
+                            title: json_string(list_item.get("name")),
+                            url: Some(url.clone()),
+                            ..StructuredItem::default()
+                        }),
+                        _ => items.push(StructuredItem {
+                            title: json_string(list_item.get("name")),
+                            url: json_url(list_item),
+                            ..StructuredItem::default()
+                        }),
+                    }
+                }
+            }
+
+            if has_type(value, ITEM_TYPES) {
+                items.push(StructuredItem {
+                    title: json_string(value.get("headline")).or_else(|| json_string(value.get("name"))),
+                    url: json_url(value),
+                    pub_date: json_string(value.get("datePublished")).or_else(|| json_string(value.get("dateCreated"))),
+                    description: json_string(value.get("description")),
+                    author: json_author(value.get("author")),
+                });
+            }
+        },
+        _ => {}
+    }
+}
+
+fn has_type(value: &Value, types: &[&str]) -> bool {
+    let matches = |type_name: &str| {
+        let type_name = type_name.trim_start_matches("http://schema.org/").trim_start_matches("https://schema.org/");
+        types.contains(&type_name)
+    };
+
+    match value.get("@type") {
+        Some(Value::String(type_name)) => matches(type_name),
+        Some(Value::Array(type_names)) => type_names.iter().filter_map(Value::as_str).any(matches),
+        _ => false,
+    }
+}
+
+fn json_array(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => vec![],
+    }
+}
+
+fn json_string(value: Option<&Value>) -> Option<String> {
+    value
+        .and_then(Value::as_str)
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Items identify themselves by `url`, or failing that the `@id` of `mainEntityOfPage`
+fn json_url(value: &Value) -> Option<String> {
+    json_string(value.get("url"))
+        .or_else(|| match value.get("mainEntityOfPage") {
+            Some(Value::String(url)) => Some(url.clone()),
+            Some(page) => json_string(page.get("@id")),
+            None => None,
+        })
+}
+
+/// Authors are a name, a `Person` or a list of either. We only use the first.
+fn json_author(value: Option<&Value>) -> Option<String> {
+    match value? {
+        Value::Array(authors) => json_author(authors.first()),
+        author @ Value::Object(_) => json_string(author.get("name")),
+        author => json_string(Some(author)),
+    }
+}
+
+fn extract_microdata_items(document: &Html) -> Vec<StructuredItem> {
+    let selector = Selector::parse("[itemscope][itemtype]").expect("selector should be valid");
+
+    document
+        .select(&selector)
+        .filter(|scope| {
+            let item_type = scope.value().attr("itemtype").unwrap_or_default();
+            item_type
+                .split_whitespace()
+                .filter_map(|url| url.rsplit('/').next())
+                .any(|type_name| ITEM_TYPES.contains(&type_name))
+        })
+        .map(|scope| StructuredItem {
+            title: microdata_property(scope, "headline").or_else(|| microdata_property(scope, "name")),
+            url: microdata_property(scope, "url"),
+            pub_date: microdata_property(scope, "datePublished"),
+            description: microdata_property(scope, "description"),
+            author: microdata_property(scope, "author"),
+        })
+        .collect()
+}
+
+/// The value of the first `itemprop="name"` of `scope`, read the same way browsers do.
+///
+/// A property whose value is an item of its own (e.g. an author `Person`) is read as that item's `name`.
+fn microdata_property(scope: ElementRef, name: &str) -> Option<String> {
+    let property = find_microdata_property(scope, name)?;
+    let element = property.value();
+
+    if element.attr("itemscope").is_some() {
+        return microdata_property(property, "name");
+    }
+
+    let value = match element.name() {
+        "a" | "link" | "area" => element.attr("href").map(str::to_string),
+        "time" => element.attr("datetime").map(str::to_string),
+        "meta" => element.attr("content").map(str::to_string),
+        _ => None,
+    };
+
+    value
+        .or_else(|| element.attr("content").map(str::to_string))
+        .or_else(|| Some(property.text().collect::<String>()))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// The first element within `scope` with the property `name`, skipping the properties of any item nested in `scope`
+fn find_microdata_property<'a>(scope: ElementRef<'a>, name: &str) -> Option<ElementRef<'a>> {
+    scope.children().filter_map(ElementRef::wrap).find_map(|child| {
+        let element = child.value();
+        let is_property = element
+            .attr("itemprop")
+            .into_iter()
+            .flat_map(str::split_whitespace)
+            .any(|property| property == name);
+
+        if is_property {
+            Some(child)
+        } else if element.attr("itemscope").is_some() {
+            None
+        } else {
+            find_microdata_property(child, name)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn extract_items_from_json_ld_and_microdata() {
+        let document = Html::parse_document(indoc! {r#"
+            <!DOCTYPE html>
+            <html>
+            <head>
+              <script type="application/ld+json">
+                {
+                  "@context": "https://schema.org",
+                  "@graph": [
+                    { "@type": "WebSite", "name": "Example" },
+                    {
+                      "@type": "BlogPosting",
+                      "headline": "The Story",
+                      "author": [{ "@type": "Person", "name": "Jane Doe" }],
+                      "mainEntityOfPage": { "@id": "https://example.com/the-story" },
+                      "datePublished": "2021-01-10T12:00:00Z"
+                    },
+                    {
+                      "@type": "ItemList",
+                      "itemListElement": [
+                        { "@type": "ListItem", "position": 1, "url": "https://example.com/listed", "name": "Listed" }
+                      ]
+                    }
+                  ]
+                }
+              </script>
+            </head>
+            <body>
+              <article itemscope itemtype="https://schema.org/NewsArticle">
+                <div itemprop="author" itemscope itemtype="https://schema.org/Person">
+                  <a itemprop="url" href="/john-roe"><span itemprop="name">John Roe</span></a>
+                </div>
+                <a itemprop="url" href="/news"><h2 itemprop="headline">The News</h2></a>
+                <time itemprop="datePublished" datetime="2021-01-11">Yesterday</time>
+              </article>
+            </body>
+        "#});
+
+        assert_eq!(extract_items(&document), vec![
+            StructuredItem {
+                title: Some("The Story".into()),
+                url: Some("https://example.com/the-story".into()),
+                pub_date: Some("2021-01-10T12:00:00Z".into()),
+                description: None,
+                author: Some("Jane Doe".into()),
+            },
+            StructuredItem {
+                title: Some("Listed".into()),
+                url: Some("https://example.com/listed".into()),
+                ..StructuredItem::default()
+            },
+            StructuredItem {
+                title: Some("The News".into()),
+                url: Some("/news".into()),
+                pub_date: Some("2021-01-11".into()),
+                description: None,
+                author: Some("John Roe".into()),
+            },
+        ]);
+    }
+}
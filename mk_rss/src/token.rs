@@ -0,0 +1,102 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{Read, Write};
+
+/// The version of the token format we produce, the first byte of every token
+const TOKEN_VERSION: u8 = 1;
+
+/// How many bytes of the HMAC we keep. 128 bits is plenty to stop forgeries and keeps urls short.
+const SIGNATURE_LENGTH: usize = 16;
+
+/// The most a token can decompress to, so a small token can't make us allocate gigabytes
+const MAX_PAYLOAD_LENGTH: u64 = 64 * 1024;
+
+/// Encode `payload` as a compact, url-safe token signed with `secret`.
+///
+/// A token is the base64url of a version byte, a truncated HMAC-SHA256 of the version and
+/// compressed payload, and the payload compressed with DEFLATE.
+pub fn encode(payload: &str, secret: &[u8]) -> String {
+    let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+    encoder.write_all(payload.as_bytes()).expect("writing to a Vec can't fail");
+    let compressed = encoder.finish().expect("writing to a Vec can't fail");
+
+    let mut token = vec![TOKEN_VERSION];
+    token.extend_from_slice(&signature(TOKEN_VERSION, &compressed, secret));
+    token.extend_from_slice(&compressed);
+
+    base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+}
+
+/// The payload of a token produced by `encode` with the same `secret`
+pub fn decode(token: &str, secret: &[u8]) -> anyhow::Result<String> {
+    let token = base64::decode_config(token.trim(), base64::URL_SAFE_NO_PAD)
+        .map_err(|_| anyhow::anyhow!("The feed token isn't valid base64url"))?;
+
+    let (version, rest) = token.split_first().ok_or_else(|| anyhow::anyhow!("The feed token is empty"))?;
+    if *version != TOKEN_VERSION {
+        return Err(anyhow::anyhow!("Version {} feed tokens aren't supported (the supported version is {})", version, TOKEN_VERSION));
+    }
+
+    if rest.len() < SIGNATURE_LENGTH {
+        return Err(anyhow::anyhow!("The feed token is too short"));
+    }
+
+    let (given_signature, compressed) = rest.split_at(SIGNATURE_LENGTH);
+
+    // Verified in constant time, so how long it takes doesn't reveal how much of the signature was right
+    mac(*version, compressed, secret)
+        .verify_truncated_left(given_signature)
+        .map_err(|_| anyhow::anyhow!("The feed token's signature is invalid"))?;
+
+    let mut payload = String::new();
+    DeflateDecoder::new(compressed)
+        .take(MAX_PAYLOAD_LENGTH)
+        .read_to_string(&mut payload)
+        .map_err(|e| anyhow::anyhow!("Could not decompress the feed token: {}", e))?;
+
+    Ok(payload)
+}
+
+fn signature(version: u8, compressed: &[u8], secret: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+    let mut signature = [0; SIGNATURE_LENGTH];
+    signature.copy_from_slice(&mac(version, compressed, secret).finalize().into_bytes()[..SIGNATURE_LENGTH]);
+    signature
+}
+
+fn mac(version: u8, compressed: &[u8], secret: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&[version]);
+    mac.update(compressed);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn decode_should_reverse_encode() {
+        let payload = "name=Example&url=https%3A%2F%2Fexample.com%2F&item_selector=article+h2";
+        let token = encode(payload, b"secret");
+
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode(&token, b"secret").unwrap(), payload);
+    }
+
+    #[test]
+    pub fn decode_should_reject_forged_and_unknown_tokens() {
+        let token = encode("name=Example", b"secret");
+        assert!(decode(&token, b"another secret").is_err());
+
+        let mut tampered = base64::decode_config(&token, base64::URL_SAFE_NO_PAD).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decode(&base64::encode_config(&tampered, base64::URL_SAFE_NO_PAD), b"secret").is_err());
+
+        tampered[0] = 2;
+        let error = decode(&base64::encode_config(&tampered, base64::URL_SAFE_NO_PAD), b"secret").unwrap_err();
+        assert!(error.to_string().starts_with("Version 2 feed tokens aren't supported"));
+    }
+}
@@ -0,0 +1,208 @@
+use roxmltree::{Document, Node};
+
+/// An item read from an existing RSS or Atom feed
+#[derive(Debug, PartialEq, Eq, Default)]
+pub struct UpstreamItem {
+    pub title: Option<String>,
+    pub link: Option<String>,
+    pub id: Option<String>,
+
+    /// Whether `id` is a url, as RSS `<guid>`s are unless they say `isPermaLink="false"`
+    pub id_is_perma_link: bool,
+    pub pub_date: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+
+    /// The url of the `<media:thumbnail>`
+    pub image: Option<String>,
+    pub enclosure: Option<UpstreamEnclosure>,
+}
+
+/// An `<enclosure>`, or an Atom `<link rel="enclosure">`
+#[derive(Debug, PartialEq, Eq)]
+pub struct UpstreamEnclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+
+    /// The size in bytes, if the feed knows it. Plenty of feeds say 0 when they don't.
+    pub length: Option<u64>,
+}
+
+/// Parse the items of an RSS 2.0, RSS 1.0 (RDF) or Atom feed, in the order they appear.
+pub fn parse_items(body: &str) -> anyhow::Result<Vec<UpstreamItem>> {
+    let document = Document::parse(body)
+        .map_err(|e| anyhow::anyhow!("Could not parse feed: {}", e))?;
+
+    let root = document.root_element();
+    match root.tag_name().name() {
+        // RSS 2.0 nests items inside `<channel>`, RSS 1.0 puts them next to it.
+        "rss" | "RDF" => Ok(root
+            .descendants()
+            .filter(|node| node.tag_name().name() == "item")
+            .map(rss_item)
+            .collect()),
+        "feed" => Ok(root
+            .children()
+            .filter(|node| node.tag_name().name() == "entry")
+            .map(atom_entry)
+            .collect()),
+        name => Err(anyhow::anyhow!("Expected an RSS or Atom feed, found <{}>", name)),
+    }
+}
+
+fn rss_item(item: Node) -> UpstreamItem {
+    UpstreamItem {
+        title: child_text(item, "title"),
+        link: child_text(item, "link").or_else(|| item.attribute(("http://www.w3.org/1999/02/22-rdf-syntax-ns#", "about")).map(str::to_string)),
+        id: child_text(item, "guid"),
+        id_is_perma_link: children(item, "guid")
+            .next()
+            .map(|guid| guid.attribute("isPermaLink") != Some("false"))
+            .unwrap_or(false),
+        pub_date: child_text(item, "pubDate").or_else(|| child_text(item, "date")),
+        description: child_text(item, "encoded").or_else(|| child_text(item, "description")),
+        // RSS `<author>` is an email address, `<dc:creator>` is usually a name
+        author: child_text(item, "creator").or_else(|| child_text(item, "author")),
+        categories: children(item, "category").filter_map(node_text).collect(),
+        image: thumbnail(item),
+        enclosure: children(item, "enclosure").find_map(|enclosure| upstream_enclosure(enclosure, "url")),
+    }
+}
+
+fn atom_entry(entry: Node) -> UpstreamItem {
+    let link = entry
+        .children()
+        .filter(|node| node.tag_name().name() == "link")
+        .find(|link| matches!(link.attribute("rel"), None | Some("alternate")))
+        .and_then(|link| link.attribute("href"))
+        .map(str::to_string);
+
+    UpstreamItem {
+        title: child_text(entry, "title"),
+        link,
+        id: child_text(entry, "id"),
+        // Atom ids are often urls, but nothing says they can be opened in a browser
+        id_is_perma_link: false,
+        pub_date: child_text(entry, "published").or_else(|| child_text(entry, "updated")),
+        description: child_text(entry, "content").or_else(|| child_text(entry, "summary")),
+        author: children(entry, "author").next().and_then(|author| child_text(author, "name")),
+        categories: children(entry, "category")
+            .filter_map(|category| category.attribute("label").or_else(|| category.attribute("term")))
+            .map(str::to_string)
+            .collect(),
+        image: thumbnail(entry),
+        enclosure: children(entry, "link")
+            .filter(|link| link.attribute("rel") == Some("enclosure"))
+            .find_map(|link| upstream_enclosure(link, "href")),
+    }
+}
+
+/// The url of the first `<media:thumbnail>` of an item, which may be inside a `<media:group>` or `<media:content>`
+fn thumbnail(item: Node) -> Option<String> {
+    item.descendants()
+        .filter(|node| node.tag_name().name() == "thumbnail")
+        .find_map(|thumbnail| thumbnail.attribute("url"))
+        .map(str::to_string)
+}
+
+/// The enclosure described by the attributes of `node`, with its url in `url_attribute`
+fn upstream_enclosure(node: Node, url_attribute: &str) -> Option<UpstreamEnclosure> {
+    Some(UpstreamEnclosure {
+        url: node.attribute(url_attribute)?.trim().to_string(),
+        mime_type: node.attribute("type").map(str::to_string),
+        length: node.attribute("length").and_then(|length| length.trim().parse().ok()).filter(|&length| length > 0),
+    })
+}
+
+/// The children of `node` named `name`, ignoring namespaces
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| child.tag_name().name() == name)
+}
+
+/// The text of the first child of `node` named `name`, ignoring namespaces
+fn child_text(node: Node, name: &str) -> Option<String> {
+    children(node, name).next().and_then(node_text)
+}
+
+fn node_text(node: Node) -> Option<String> {
+    let text = node.descendants().filter(Node::is_text).filter_map(|text| text.text()).collect::<String>();
+    Some(text.trim().to_string()).filter(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    pub fn parse_rss_and_atom_items() {
+        let rss = parse_items(indoc! {r#"
+            <?xml version="1.0"?>
+            <rss version="2.0" xmlns:media="http://search.yahoo.com/mrss/">
+            <channel>
+              <title>Example</title>
+              <item>
+                <title>The Story</title>
+                <link>https://example.com/the-story</link>
+                <guid isPermaLink="false">story-1</guid>
+                <pubDate>Sun, 10 Jan 2021 12:00:00 GMT</pubDate>
+                <description><![CDATA[A <em>story</em>]]></description>
+                <category>News</category>
+                <category>Rust</category>
+                <enclosure url="https://example.com/episode-1.mp3" length="1234" type="audio/mpeg"/>
+                <media:thumbnail url="https://example.com/episode-1.jpg"/>
+              </item>
+            </channel>
+            </rss>
+        "#}).unwrap();
+
+        assert_eq!(rss, vec![UpstreamItem {
+            title: Some("The Story".into()),
+            link: Some("https://example.com/the-story".into()),
+            id: Some("story-1".into()),
+            id_is_perma_link: false,
+            pub_date: Some("Sun, 10 Jan 2021 12:00:00 GMT".into()),
+            description: Some("A <em>story</em>".into()),
+            author: None,
+            categories: vec!["News".into(), "Rust".into()],
+            image: Some("https://example.com/episode-1.jpg".into()),
+            enclosure: Some(UpstreamEnclosure {
+                url: "https://example.com/episode-1.mp3".into(),
+                mime_type: Some("audio/mpeg".into()),
+                length: Some(1234),
+            }),
+        }]);
+
+        let atom = parse_items(indoc! {r#"
+            <?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+              <title>Example</title>
+              <entry>
+                <title>The Story</title>
+                <link rel="edit" href="https://example.com/edit/1"/>
+                <link href="https://example.com/the-story"/>
+                <link rel="enclosure" href="https://example.com/episode-1.ogg" length="0" type="audio/ogg"/>
+                <id>urn:uuid:1</id>
+                <updated>2021-01-10T12:00:00Z</updated>
+                <summary>A story</summary>
+                <author><name>Jane Doe</name><email>jane@example.com</email></author>
+                <category term="rust"/>
+              </entry>
+            </feed>
+        "#}).unwrap();
+
+        assert_eq!(atom, vec![UpstreamItem {
+            title: Some("The Story".into()),
+            link: Some("https://example.com/the-story".into()),
+            id: Some("urn:uuid:1".into()),
+            id_is_perma_link: false,
+            pub_date: Some("2021-01-10T12:00:00Z".into()),
+            description: Some("A story".into()),
+            author: Some("Jane Doe".into()),
+            categories: vec!["rust".into()],
+            image: None,
+            enclosure: Some(UpstreamEnclosure { url: "https://example.com/episode-1.ogg".into(), mime_type: Some("audio/ogg".into()), length: None }),
+        }]);
+    }
+}
@@ -0,0 +1,230 @@
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// A comma separated list of domains that may be fetched, every other domain is refused. Subdomains are included.
+const ALLOWED_HOSTS_VARIABLE: &str = "MK_RSS_ALLOWED_HOSTS";
+
+/// A comma separated list of domains that may never be fetched. Subdomains are included.
+const DENIED_HOSTS_VARIABLE: &str = "MK_RSS_DENIED_HOSTS";
+
+/// Set to "true" to allow fetching from loopback, private and link-local addresses, e.g. to scrape an intranet from the CLI
+const ALLOW_PRIVATE_ADDRESSES_VARIABLE: &str = "MK_RSS_ALLOW_PRIVATE_ADDRESSES";
+
+/// The policy every fetch is checked against, read from the environment once per process
+pub static URL_POLICY: Lazy<UrlPolicy> = Lazy::new(UrlPolicy::from_env);
+
+/// Which urls we're willing to fetch.
+///
+/// Anyone who can reach the lambda can ask it to fetch any url, so by default we refuse anything
+/// that isn't HTTP(S) on a public address, e.g. cloud metadata endpoints and internal services.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct UrlPolicy {
+    /// If not empty, only these domains (and their subdomains) may be fetched
+    pub allowed_hosts: Vec<String>,
+
+    /// These domains (and their subdomains) may never be fetched
+    pub denied_hosts: Vec<String>,
+
+    /// Whether loopback, private, link-local and other non-public addresses may be fetched
+    pub allow_private_addresses: bool,
+}
+
+impl UrlPolicy {
+    /// The policy configured by the `MK_RSS_ALLOWED_HOSTS`, `MK_RSS_DENIED_HOSTS` and `MK_RSS_ALLOW_PRIVATE_ADDRESSES` environment variables
+    pub fn from_env() -> UrlPolicy {
+        let hosts = |variable: &str| -> Vec<String> {
+            env::var(variable)
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().trim_start_matches("*.").trim_end_matches('.').to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect()
+        };
+
+        UrlPolicy {
+            allowed_hosts: hosts(ALLOWED_HOSTS_VARIABLE),
+            denied_hosts: hosts(DENIED_HOSTS_VARIABLE),
+            allow_private_addresses: env::var(ALLOW_PRIVATE_ADDRESSES_VARIABLE).map(|value| value == "true").unwrap_or(false),
+        }
+    }
+
+    /// Check that `url` may be fetched, resolving its host to make sure it isn't a private address.
+    ///
+    /// Returns the address the host resolved to, which should be used to connect so the host can't
+    /// resolve to somewhere else by the time we do (DNS rebinding). IP addresses don't need resolving.
+    pub async fn check(&self, url: &Url) -> anyhow::Result<Option<SocketAddr>> {
+        let refuse = |reason: String| anyhow::anyhow!("Refusing to fetch {}: {}", url, reason);
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(refuse(format!("{} urls aren't allowed", url.scheme())));
+        }
+
+        let host = url.host_str().ok_or_else(|| refuse("it has no host".into()))?;
+        let domain = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_lowercase();
+
+        if self.denied_hosts.iter().any(|denied| is_within(&domain, denied)) {
+            return Err(refuse(format!("{} is denied", domain)));
+        }
+
+        if !self.allowed_hosts.is_empty() && !self.allowed_hosts.iter().any(|allowed| is_within(&domain, allowed)) {
+            return Err(refuse(format!("{} isn't in the list of allowed hosts", domain)));
+        }
+
+        if self.allow_private_addresses {
+            return Ok(None);
+        }
+
+        if let Ok(address) = domain.parse::<IpAddr>() {
+            if !is_public(address) {
+                return Err(refuse(format!("{} isn't a public address", address)));
+            }
+
+            return Ok(None);
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addresses: Vec<SocketAddr> = tokio::net::lookup_host((domain.as_str(), port))
+            .await
+            .map_err(|e| refuse(format!("could not resolve {}: {}", domain, e)))?
+            .collect();
+
+        // Every address has to be public, otherwise we'd be at the mercy of which one gets picked
+        if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+            return Err(refuse(format!("{} resolves to {}, which isn't a public address", domain, address.ip())));
+        }
+
+        match addresses.first() {
+            Some(address) => Ok(Some(*address)),
+            None => Err(refuse(format!("{} doesn't resolve to any address", domain))),
+        }
+    }
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn is_within(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+/// Whether `address` is reachable on the public internet, rather than loopback, private, link-local, multicast or reserved
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => is_public_v6(address),
+    }
+}
+
+fn is_public_v4(address: Ipv4Addr) -> bool {
+    let [a, b, c, _] = address.octets();
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_private()
+        || address.is_link_local()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        // "This network"
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0b1111_1110) == 18)
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(address: Ipv6Addr) -> bool {
+    let segments = address.segments();
+
+    let v4 = |high: u16, low: u16| Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8);
+
+    // IPv4-mapped (::ffff:0:0/96), IPv4-compatible (::a.b.c.d), NAT64 (64:ff9b::/96) and 6to4
+    // (2002::/16) addresses reach the IPv4 address they embed. `::` and `::1` aren't IPv4-compatible.
+    let embedded_v4 = match segments {
+        [0, 0, 0, 0, 0, 0xffff, high, low] | [0x64, 0xff9b, 0, 0, 0, 0, high, low] => Some(v4(high, low)),
+        [0, 0, 0, 0, 0, 0, high, low] if high != 0 || low > 1 => Some(v4(high, low)),
+        [0x2002, high, low, _, _, _, _, _] => Some(v4(high, low)),
+        _ => None,
+    };
+
+    if let Some(embedded_v4) = embedded_v4 {
+        return is_public_v4(embedded_v4);
+    }
+
+    !(address.is_unspecified()
+        || address.is_loopback()
+        || address.is_multicast()
+        // Unique local, fc00::/7
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Site-local (deprecated), fec0::/10
+        || (segments[0] & 0xffc0) == 0xfec0
+        // Documentation, 2001:db8::/32
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(policy: &UrlPolicy, url: &str) -> Result<Option<SocketAddr>, String> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(policy.check(&Url::parse(url).unwrap())).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    pub fn check_should_refuse_private_addresses_and_other_schemes() {
+        let policy = UrlPolicy::default();
+
+        assert_eq!(check(&policy, "https://93.184.216.34/feed"), Ok(None));
+        assert_eq!(check(&policy, "http://[2606:2800:220:1::]/"), Ok(None));
+        assert_eq!(check(&policy, "http://[2002:5db8:d822::]/"), Ok(None));
+
+        for url in &[
+            "http://169.254.169.254/latest/meta-data/",
+            "http://127.0.0.1:8080/",
+            "http://10.0.0.1/",
+            "http://172.16.5.4/",
+            "http://192.168.1.1/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:169.254.169.254]/",
+            "http://[::169.254.169.254]/",
+            "http://[::7f00:1]/",
+            "http://[2002:a9fe:a9fe::]/",
+            "http://[2002:7f00:1::1]/",
+            "file:///etc/passwd",
+            "ftp://example.com/",
+            "http://localhost/",
+        ] {
+            assert!(check(&policy, url).is_err(), "{} should be refused", url);
+        }
+
+        let permissive = UrlPolicy { allow_private_addresses: true, ..UrlPolicy::default() };
+        assert_eq!(check(&permissive, "http://127.0.0.1:8080/"), Ok(None));
+        assert!(check(&permissive, "file:///etc/passwd").is_err());
+    }
+
+    #[test]
+    pub fn check_should_apply_the_allowed_and_denied_hosts() {
+        let policy = UrlPolicy {
+            allowed_hosts: vec!["example.com".into()],
+            denied_hosts: vec!["private.example.com".into()],
+            allow_private_addresses: true,
+        };
+
+        assert_eq!(check(&policy, "https://example.com/"), Ok(None));
+        assert_eq!(check(&policy, "https://news.example.com/"), Ok(None));
+        assert!(check(&policy, "https://notexample.com/").is_err());
+        assert!(check(&policy, "https://private.example.com/").is_err());
+        assert!(check(&policy, "https://a.private.example.com/").is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use reqwest::{StatusCode, Url};
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::feed::{Feed, FeedItem};
+
+/// Discord rejects messages with more embeds than this
+const MAX_DISCORD_EMBEDS: usize = 10;
+
+/// Discord rejects embeds with titles longer than this many characters
+const MAX_DISCORD_TITLE_CHARS: usize = 256;
+
+/// The shape of the JSON we POST to a webhook
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum WebhookFormat {
+    /// The feed and every new item, with all the detail we have
+    Json,
+
+    /// A message for a Slack incoming webhook
+    Slack,
+
+    /// A message for a Discord webhook
+    Discord,
+}
+
+/// Where and how to send new items
+#[derive(Debug, PartialEq, Clone)]
+pub struct Webhook {
+    pub url: Url,
+    pub format: WebhookFormat,
+
+    /// How many times to retry a failed POST before giving up
+    pub retries: u32,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Webhook { url, format: WebhookFormat::Json, retries: 3 }
+    }
+
+    /// POST `items`, which are new in `feed`, to this webhook, in as many requests as the format needs.
+    ///
+    /// If any request fails every item counts as undelivered, so they're all sent again next time
+    /// rather than risk losing some.
+    pub async fn notify(&self, feed: &Feed, items: &[&FeedItem]) -> anyhow::Result<()> {
+        let client = reqwest::Client::new();
+        for payload in self.format.payloads(feed, items) {
+            self.post(&client, &payload).await?;
+        }
+
+        Ok(())
+    }
+
+    /// POST `payload` to this webhook.
+    ///
+    /// Network errors, rate limits and server errors are retried with exponential backoff,
+    /// any other error means the webhook will never accept this payload so we give up immediately.
+    async fn post(&self, client: &reqwest::Client, payload: &Value) -> anyhow::Result<()> {
+        let mut delay = Duration::from_millis(500);
+        let mut attempt = 0;
+
+        loop {
+            let response = client
+                .post(self.url.clone())
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(payload.to_string())
+                .send()
+                .await;
+
+            let error = match response {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let error = anyhow::anyhow!("{} responded with {}", self.url, status);
+                    if !(status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS) {
+                        return Err(error);
+                    }
+
+                    error
+                },
+                Err(e) => anyhow::anyhow!("Could not reach {}: {}", self.url, e),
+            };
+
+            if attempt >= self.retries {
+                return Err(error);
+            }
+
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+impl WebhookFormat {
+    /// The JSON to POST for `items`, which are new in `feed`. Discord only takes so many items per
+    /// message, so they're split over several.
+    pub fn payloads(&self, feed: &Feed, items: &[&FeedItem]) -> Vec<Value> {
+        match self {
+            WebhookFormat::Json => vec![json!({
+                "feed": { "name": feed.name, "url": feed.url.as_str() },
+                "items": items.iter().map(|item| item_json(item)).collect::<Vec<Value>>(),
+            })],
+            WebhookFormat::Slack => {
+                let lines: Vec<String> = items
+                    .iter()
+                    .map(|item| format!("• <{}|{}>", item.url, slack_escape(&item.title)))
+                    .collect();
+
+                vec![json!({
+                    "text": format!("{} in *{}*\n{}", new_items_summary(items), slack_escape(&feed.name), lines.join("\n")),
+                    "unfurl_links": false,
+                })]
+            },
+            WebhookFormat::Discord => items
+                .chunks(MAX_DISCORD_EMBEDS)
+                .enumerate()
+                .map(|(index, chunk)| {
+                    let embeds: Vec<Value> = chunk.iter().map(|item| discord_embed(item)).collect();
+
+                    // Only the first message says what's new, the rest carry on from it
+                    if index == 0 {
+                        json!({
+                            "content": format!("{} in **{}**", new_items_summary(items), feed.name),
+                            "embeds": embeds,
+                        })
+                    } else {
+                        json!({ "embeds": embeds })
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+fn discord_embed(item: &FeedItem) -> Value {
+    let mut embed = json!({
+        "title": truncate(&item.title, MAX_DISCORD_TITLE_CHARS),
+        "url": item.url.as_str(),
+        "timestamp": item.pub_date.to_rfc3339(),
+    });
+
+    if let Some(author) = &item.author {
+        embed["author"] = json!({ "name": author });
+    }
+
+    if let Some(image) = &item.image {
+        embed["thumbnail"] = json!({ "url": image.as_str() });
+    }
+
+    embed
+}
+
+/// `text` cut down to at most `max_chars` characters, ending in an ellipsis if anything was cut
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn item_json(item: &FeedItem) -> Value {
+    json!({
+        "title": item.title,
+        "url": item.url.as_str(),
+        "guid": item.guid.value,
+        "pub_date": item.pub_date.to_rfc3339(),
+        "description": item.description,
+        "author": item.author,
+        "categories": item.categories,
+        "image": item.image.as_ref().map(Url::as_str),
+        "enclosure": item.enclosure.as_ref().map(|enclosure| enclosure.url.as_str()),
+    })
+}
+
+fn new_items_summary(items: &[&FeedItem]) -> String {
+    match items.len() {
+        1 => "1 new item".to_string(),
+        count => format!("{} new items", count),
+    }
+}
+
+/// Slack treats `&`, `<` and `>` as control characters in message text
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl TryFrom<&str> for WebhookFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "json" => Ok(WebhookFormat::Json),
+            "slack" => Ok(WebhookFormat::Slack),
+            "discord" => Ok(WebhookFormat::Discord),
+            _ => Err(anyhow::anyhow!("{} is not a valid webhook format (valid formats are 'json', 'slack' and 'discord')", value))
+        }
+    }
+}
+
+impl FromStr for WebhookFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::try_from(value)
+    }
+}
+
+impl fmt::Display for WebhookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebhookFormat::Json => write!(f, "json"),
+            WebhookFormat::Slack => write!(f, "slack"),
+            WebhookFormat::Discord => write!(f, "discord"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn feed() -> Feed {
+        let item = FeedItem { author: Some("Jane Doe".into()), ..FeedItem::for_test("The <Story>", "https://example.com/the-story") };
+        Feed::for_test("Example", vec![item])
+    }
+
+    /// A webhook that answers each request with the next of `statuses`, recording every request body it receives
+    async fn serve_webhook(statuses: Vec<u16>) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 4096];
+
+                // Read until we have the headers and the whole body they promise
+                loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let content_length = headers
+                            .lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+
+                        if body.len() >= content_length || read == 0 {
+                            received.lock().unwrap().push(body.to_string());
+                            break;
+                        }
+                    }
+                }
+
+                let response = format!("HTTP/1.1 {} Webhook\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    pub async fn notify_should_retry_server_errors() {
+        let (url, requests) = serve_webhook(vec![503, 200]).await;
+        let feed = feed();
+        let items: Vec<&FeedItem> = feed.items.iter().collect();
+
+        Webhook::new(url).notify(&feed, &items).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+
+        let payload: Value = serde_json::from_str(&requests[1]).unwrap();
+        assert_eq!(payload["items"][0]["url"], "https://example.com/the-story");
+    }
+
+    #[tokio::test]
+    pub async fn notify_should_not_retry_client_errors() {
+        let (url, requests) = serve_webhook(vec![404, 200]).await;
+        let feed = feed();
+        let items: Vec<&FeedItem> = feed.items.iter().collect();
+
+        assert!(Webhook::new(url).notify(&feed, &items).await.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    pub fn slack_and_discord_payloads() {
+        let feed = feed();
+        let items: Vec<&FeedItem> = feed.items.iter().collect();
+
+        let slack = WebhookFormat::Slack.payloads(&feed, &items);
+        assert_eq!(slack[0]["text"], "1 new item in *Example*\n• <https://example.com/the-story|The &lt;Story&gt;>");
+
+        let discord = WebhookFormat::Discord.payloads(&feed, &items);
+        assert_eq!(discord[0]["content"], "1 new item in **Example**");
+        assert_eq!(discord[0]["embeds"][0]["author"]["name"], "Jane Doe");
+    }
+
+    #[tokio::test]
+    pub async fn discord_notifications_should_deliver_every_item() {
+        let (url, requests) = serve_webhook(vec![200, 200, 200]).await;
+        let mut feed = feed();
+        feed.items = (0..25)
+            .map(|i| FeedItem::for_test(&format!("{} {}", i, "a".repeat(300)), &format!("https://example.com/story-{}", i)))
+            .collect();
+        let items: Vec<&FeedItem> = feed.items.iter().collect();
+
+        let webhook = Webhook { format: WebhookFormat::Discord, ..Webhook::new(url) };
+        webhook.notify(&feed, &items).await.unwrap();
+
+        let payloads: Vec<Value> = requests.lock().unwrap().iter().map(|body| serde_json::from_str(body).unwrap()).collect();
+        let embed_counts: Vec<usize> = payloads.iter().map(|payload| payload["embeds"].as_array().unwrap().len()).collect();
+        assert_eq!(embed_counts, vec![10, 10, 5]);
+        assert_eq!(payloads[0]["content"], "25 new items in **Example**");
+
+        let title = payloads[2]["embeds"][4]["title"].as_str().unwrap();
+        assert_eq!(title.chars().count(), 256);
+        assert!(title.starts_with("24 ") && title.ends_with('…'));
+    }
+}
@@ -1,34 +1,98 @@
 use reqwest::Url;
 use scraper::Html;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
 use chrono_english::Dialect;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use sxd_document::Package;
 
-use super::feed_request::{FeedRequest, FeedOrder};
+use super::channel::ChannelMetadata;
+use super::feed_request::{FeedRequest, FeedOrder, FeedSource, HtmlSelectors, JsonPaths};
+use super::guid::Guid;
+use super::html_selector::SelectorContext;
+use super::media::{self, Enclosure};
+use super::rewrite::RewriteRules;
+use super::sitemap::{self, SitemapEntry};
+use super::structured_data::{self, StructuredItem};
+use super::upstream_feed::{self, UpstreamEnclosure};
 
 #[derive(Debug, PartialEq)]
 pub struct Website {
     pub name: String,
     pub url: Url,
-    pub elements: Vec<WebsiteElement>
+    pub elements: Vec<WebsiteElement>,
+
+    /// What the request and the page say about the feed as a whole
+    pub channel: ChannelMetadata
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct WebsiteElement {
     pub title: String,
     pub url: Url,
-    pub pub_date: Option<DateTime<Local>>
+    pub pub_date: Option<DateTime<Local>>,
+    pub description: Option<String>,
+    pub guid: Guid,
+    pub image: Option<Url>,
+    pub enclosure: Option<Enclosure>,
+    pub author: Option<String>,
+    pub categories: Vec<String>
+}
+
+/// The parts of an item as they appear in the scraped document, before we've made
+/// any sense of them.
+struct ScrapedItem<'a> {
+    title: String,
+    link: String,
+    pub_date: Option<String>,
+    description: Option<String>,
+    guid_attribute: Option<&'a str>,
+    image: Option<String>,
+    enclosure: Option<String>,
+    author: Option<String>,
+    categories: Vec<String>,
 }
 
 impl Website {
-    /// Scrape a `Website` from `html_body`
-    pub fn scrape(request: &FeedRequest, html_body: &str, now: DateTime<Local>) -> Website {
-        let mut items = Website::scrape_items(request, html_body, now);
+    /// Scrape a `Website` from `body`
+    pub fn scrape(request: &FeedRequest, body: &str, now: DateTime<Local>) -> anyhow::Result<Website> {
+        // Pages are only parsed once, for both their items and what they say about the feed
+        let (items, page_metadata) = match &request.source {
+            FeedSource::Html(selectors) => {
+                let document = Html::parse_document(body);
+                let items = Website::scrape_html_items(request, selectors, &document, now);
+                (items, ChannelMetadata::from_document(&document, &request.url))
+            },
+            FeedSource::Json(paths) => (Website::scrape_json_items(request, paths, body, now)?, ChannelMetadata::default()),
+            FeedSource::StructuredData => {
+                let document = Html::parse_document(body);
+                let items = Website::scrape_structured_data_items(request, &document, now);
+                (items, ChannelMetadata::from_document(&document, &request.url))
+            },
+            FeedSource::Feed => (Website::scrape_feed_items(request, body, now)?, ChannelMetadata::default()),
+            FeedSource::Sitemap(options) => {
+                // We can only follow sitemap indexes and fetch page titles when we're allowed to fetch, see `sitemap::fetch_website`
+                let sitemap = sitemap::parse(body, &request.url, now)?;
+                let entries = sitemap::select_entries(sitemap.entries, options, request.max_items);
+                (Website::sitemap_items(request, entries, now), ChannelMetadata::default())
+            },
+        };
 
+        Ok(Website::from_items(request, items, page_metadata))
+    }
+
+    /// Build a `Website` from entries already selected from a sitemap
+    pub fn from_sitemap_entries(request: &FeedRequest, entries: Vec<SitemapEntry>, now: DateTime<Local>) -> Website {
+        let items = Website::sitemap_items(request, entries, now);
+        Website::from_items(request, items, ChannelMetadata::default())
+    }
+
+    fn from_items(request: &FeedRequest, mut items: Vec<WebsiteElement>, page_metadata: ChannelMetadata) -> Website {
         if request.order == FeedOrder::Reversed {
             items.reverse();
         }
 
-        let items: Vec<WebsiteElement> = items
+        let items: Vec<WebsiteElement> = Website::dedupe_items(items)
             .into_iter()
             .take(request.max_items)
             .collect();
@@ -36,52 +100,412 @@ impl Website {
         Website {
             name: request.name.clone(),
             url: request.url.clone(),
-            elements: items
+            elements: items,
+            channel: request.channel.clone().or(page_metadata)
         }
     }
 
-    fn scrape_items(request: &FeedRequest, html_body: &str, now: DateTime<Local>) -> Vec<WebsiteElement> {
-        let document = Html::parse_document(html_body);
+    fn scrape_html_items(
+        request: &FeedRequest,
+        selectors: &HtmlSelectors,
+        document: &Html,
+        now: DateTime<Local>
+    ) -> Vec<WebsiteElement> {
+        let package = Package::new();
+        let context = SelectorContext::new(document, &package, selectors.needs_xpath());
 
-        document
-            .select(&request.item_selector)
+        let items = context
+            .select_all(&selectors.item_selector)
+            .into_iter()
             .filter_map(|item| {
-                let title_node = request.title_selector
+                let title_node = selectors.title_selector
                     .as_ref()
-                    .and_then(|s| item.select(&s).next())
+                    .and_then(|s| context.select_first(s, item))
                     .unwrap_or(item);
 
                 let title = title_node.text().collect::<String>().trim().to_string();
-                let link_node = request.link_selector
+                let link_node = selectors.link_selector
                     .as_ref()
-                    .and_then(|s| item.select(&s).next())
+                    .and_then(|s| context.select_first(s, item))
                     .unwrap_or(item);
 
-                let url = link_node.value().attr("href")?.to_string();
-                let absolute_url = Url::parse(&url)
-                    .ok()
-                    .or_else(|| request.url.join(&url).ok())?;
+                let link = link_node.value().attr("href")?.to_string();
 
-                let pub_date_node = request.pub_date_selector
+                let pub_date_node = selectors.pub_date_selector
                     .as_ref()
-                    .and_then(|s| item.select(&s).next())
+                    .and_then(|s| context.select_first(s, item))
                     .unwrap_or(item);
 
-                let pub_date_text = pub_date_node.text().collect::<String>().trim().to_string();
-                let pub_date = chrono_english::parse_date_string(&pub_date_text, now, Dialect::Uk).ok();
+                let pub_date = pub_date_node.text().collect::<String>().trim().to_string();
+
+                let description = selectors.description_selector
+                    .as_ref()
+                    .and_then(|s| context.select_first(s, item))
+                    .map(|node| node.inner_html().trim().to_string());
+
+                let image = selectors.image_selector
+                    .as_ref()
+                    .and_then(|s| context.select_first(s, item))
+                    .and_then(media::media_url);
+
+                let enclosure = selectors.enclosure_selector
+                    .as_ref()
+                    .and_then(|s| context.select_first(s, item))
+                    .and_then(media::media_url);
+
+                let author = selectors.author_selector
+                    .as_ref()
+                    .and_then(|s| context.select_first(s, item))
+                    .map(|node| node.text().collect::<String>().trim().to_string());
+
+                let categories = selectors.category_selector
+                    .as_ref()
+                    .map(|s| context.select_all_within(s, item))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|node| node.text().collect::<String>().trim().to_string())
+                    .collect();
+
+                let guid_attribute = request.guid_strategy
+                    .attribute()
+                    .and_then(|attribute| item.value().attr(attribute).or_else(|| link_node.value().attr(attribute)));
+
+                let scraped_item = ScrapedItem {
+                    title,
+                    link,
+                    pub_date: Some(pub_date),
+                    description,
+                    guid_attribute,
+                    image,
+                    enclosure,
+                    author,
+                    categories,
+                };
+
+                scraped_item.into_element(request, now)
+            })
+            .collect();
+
+        Website::enrich_with_structured_data(request, items, document, now)
+    }
+
+    fn scrape_structured_data_items(request: &FeedRequest, document: &Html, now: DateTime<Local>) -> Vec<WebsiteElement> {
+        structured_data::extract_items(document)
+            .into_iter()
+            .filter_map(|item| {
+                let scraped_item = ScrapedItem {
+                    title: item.title.unwrap_or_default(),
+                    link: item.url?,
+                    pub_date: item.pub_date,
+                    description: item.description,
+                    guid_attribute: None,
+                    image: None,
+                    enclosure: None,
+                    author: item.author,
+                    categories: vec![],
+                };
+
+                scraped_item.into_element(request, now)
+            })
+            .collect()
+    }
+
+    fn scrape_feed_items(request: &FeedRequest, feed_body: &str, now: DateTime<Local>) -> anyhow::Result<Vec<WebsiteElement>> {
+        let items = upstream_feed::parse_items(feed_body)
+            .map_err(|e| anyhow::anyhow!("Could not read {}: {}", request.url, e))?;
+
+        let items = items
+            .into_iter()
+            .filter_map(|item| {
+                let link = item.link?;
+                let upstream_enclosure = item.enclosure;
+                let has_id = item.id.as_deref().map(str::trim).filter(|id| !id.is_empty()).is_some();
+                let scraped_item = ScrapedItem {
+                    title: item.title.unwrap_or_default(),
+                    link,
+                    pub_date: item.pub_date,
+                    description: item.description,
+                    // With `attribute:guid` we keep the upstream feeds guid
+                    guid_attribute: item.id.as_deref(),
+                    image: item.image,
+                    enclosure: upstream_enclosure.as_ref().map(|enclosure| enclosure.url.clone()),
+                    author: item.author,
+                    categories: item.categories,
+                };
+
+                let element = scraped_item.into_element(request, now)?;
+
+                // The upstream feed knows better than our guess from the file extension
+                let enclosure = element.enclosure.map(|enclosure| match upstream_enclosure {
+                    Some(UpstreamEnclosure { mime_type, length, .. }) => Enclosure {
+                        mime_type: mime_type.or(enclosure.mime_type),
+                        length,
+                        ..enclosure
+                    },
+                    None => enclosure,
+                });
+
+                // `guid_for` can't know whether an attribute is a url, but the upstream feed says so
+                let guid = if has_id {
+                    Guid { is_perma_link: item.id_is_perma_link, ..element.guid }
+                } else {
+                    element.guid
+                };
+
+                Some(WebsiteElement { enclosure, guid, ..element })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    fn sitemap_items(request: &FeedRequest, entries: Vec<SitemapEntry>, now: DateTime<Local>) -> Vec<WebsiteElement> {
+        entries
+            .into_iter()
+            .filter_map(|entry| {
+                let SitemapEntry { url, lastmod, title } = entry;
+                let scraped_item = ScrapedItem {
+                    title: title.unwrap_or_else(|| sitemap::title_from_url(&url)),
+                    link: url.to_string(),
+                    pub_date: None,
+                    description: None,
+                    guid_attribute: None,
+                    image: None,
+                    enclosure: None,
+                    author: None,
+                    categories: vec![],
+                };
+
+                let element = scraped_item.into_element(request, now)?;
+                Some(WebsiteElement { pub_date: lastmod, ..element })
+            })
+            .collect()
+    }
+
+    /// Human-readable dates on a page are often vague ("3 days ago") so when the page also describes
+    /// its items with structured data we prefer the exact dates from there.
+    fn enrich_with_structured_data(
+        request: &FeedRequest,
+        items: Vec<WebsiteElement>,
+        document: &Html,
+        now: DateTime<Local>
+    ) -> Vec<WebsiteElement> {
+        let exact_pub_dates: HashMap<String, DateTime<Local>> = structured_data::extract_items(document)
+            .into_iter()
+            .filter_map(|StructuredItem { url, pub_date, .. }| {
+                let url = request.url.join(&url?).ok()?;
+                let url = request.rewrite.rewrite_url(url);
+                let pub_date = parse_pub_date(&pub_date?, now)?;
 
-                Some(WebsiteElement { title, url: absolute_url, pub_date })
+                Some((normalize_url(&url), pub_date))
+            })
+            .collect();
+
+        if exact_pub_dates.is_empty() {
+            return items;
+        }
+
+        items
+            .into_iter()
+            .map(|item| match exact_pub_dates.get(&normalize_url(&item.url)) {
+                Some(pub_date) => WebsiteElement { pub_date: Some(*pub_date), ..item },
+                None => item,
             })
             .collect()
     }
+
+    fn scrape_json_items(
+        request: &FeedRequest,
+        paths: &JsonPaths,
+        json_body: &str,
+        now: DateTime<Local>
+    ) -> anyhow::Result<Vec<WebsiteElement>> {
+        let document: Value = serde_json::from_str(json_body)
+            .map_err(|e| anyhow::anyhow!("Could not parse {} as JSON: {}", request.url, e))?;
+
+        let items = paths.item_path
+            .query(&document)
+            .all()
+            .into_iter()
+            .filter_map(|item| {
+                let text_at = |path: &serde_json_path::JsonPath| path
+                    .query(item)
+                    .first()
+                    .and_then(json_text);
+
+                let title = paths.title_path.as_ref().and_then(text_at).unwrap_or_default();
+                let link = match &paths.link_path {
+                    Some(path) => text_at(path)?,
+                    None => json_text(item)?,
+                };
+
+                // Only a JSON number is a unix timestamp, digits in a string could be anything
+                let timestamp = paths.pub_date_path
+                    .as_ref()
+                    .and_then(|path| path.query(item).first())
+                    .and_then(Value::as_i64)
+                    .and_then(timestamp_pub_date);
+                let pub_date = paths.pub_date_path.as_ref().and_then(text_at).filter(|_| timestamp.is_none());
+                let description = paths.description_path.as_ref().and_then(text_at);
+                let image = paths.image_path.as_ref().and_then(text_at);
+                let enclosure = paths.enclosure_path.as_ref().and_then(text_at);
+                let author = paths.author_path.as_ref().and_then(text_at);
+                let categories = paths.category_path
+                    .as_ref()
+                    .map(|path| path.query(item).all().into_iter().filter_map(json_text).collect())
+                    .unwrap_or_default();
+
+                let guid_text = request.guid_strategy
+                    .attribute()
+                    .and_then(|attribute| item.get(attribute))
+                    .and_then(json_text);
+
+                let scraped_item = ScrapedItem {
+                    title,
+                    link,
+                    pub_date,
+                    description,
+                    guid_attribute: guid_text.as_deref(),
+                    image,
+                    enclosure,
+                    author,
+                    categories,
+                };
+
+                let element = scraped_item.into_element(request, now)?;
+                Some(WebsiteElement { pub_date: timestamp.or(element.pub_date), ..element })
+            })
+            .collect();
+
+        Ok(items)
+    }
+
+    /// Pages often link to the same item more than once (e.g. a pinned post that also
+    /// appears in the regular listing). We only keep the first occurrence of each url.
+    fn dedupe_items(items: Vec<WebsiteElement>) -> Vec<WebsiteElement> {
+        let mut seen_urls = HashSet::new();
+
+        items
+            .into_iter()
+            .filter(|item| seen_urls.insert(normalize_url(&item.url)))
+            .collect()
+    }
+}
+
+/// Normalize `url` for comparison by dropping the fragment and any trailing `/` on the path
+pub fn normalize_url(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+
+    let path = url.path().trim_end_matches('/').to_string();
+    if !path.is_empty() {
+        url.set_path(&path);
+    }
+
+    url.to_string()
+}
+
+/// The text of a JSON string or number, since sites are inconsistent about which they use for ids and dates
+fn json_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Parse a publish date written by a human (`Jan 10, 2021`, `3 days ago`) or a machine
+/// (RFC 3339, RFC 2822 or ISO 8601 without a timezone).
+pub fn parse_pub_date(text: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(text).or_else(|_| DateTime::parse_from_rfc2822(text)) {
+        return Some(date.with_timezone(&Local));
+    }
+
+    if let Ok(date) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S") {
+        return Local.from_local_datetime(&date).earliest();
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Local.from_local_date(&date).and_hms_opt(0, 0, 0).earliest();
+    }
+
+    chrono_english::parse_date_string(text, now, Dialect::Uk).ok()
+}
+
+/// The date of a unix timestamp in seconds or milliseconds
+fn timestamp_pub_date(timestamp: i64) -> Option<DateTime<Local>> {
+    // Anything this large is in milliseconds, otherwise we'd be thousands of years in the future
+    let seconds = if timestamp > 100_000_000_000 { timestamp / 1000 } else { timestamp };
+    Local.timestamp_opt(seconds, 0).single()
+}
+
+impl ScrapedItem<'_> {
+    fn into_element(self, request: &FeedRequest, now: DateTime<Local>) -> Option<WebsiteElement> {
+        let url = Url::parse(&self.link)
+            .ok()
+            .or_else(|| request.url.join(&self.link).ok())?;
+
+        let pub_date = self.pub_date
+            .as_deref()
+            .and_then(|text| parse_pub_date(text, now));
+
+        let image = self.image.and_then(|image| request.url.join(&image).ok());
+        let enclosure = self.enclosure
+            .and_then(|enclosure| request.url.join(&enclosure).ok())
+            .map(Enclosure::new);
+
+        let author = self.author.filter(|author| !author.is_empty());
+        let mut categories: Vec<String> = vec![];
+        for category in self.categories {
+            if !category.is_empty() && !categories.contains(&category) {
+                categories.push(category);
+            }
+        }
+
+        let guid = Guid::permalink(&url);
+        let element = WebsiteElement {
+            title: self.title,
+            url,
+            pub_date,
+            description: self.description,
+            guid,
+            image,
+            enclosure,
+            author,
+            categories,
+        };
+
+        let element = element.rewrite(&request.rewrite);
+
+        let guid = request.guid_strategy.guid_for(&element.title, &element.url, self.guid_attribute);
+        Some(WebsiteElement { guid, ..element })
+    }
+}
+
+impl WebsiteElement {
+    /// Clean up this elements title and url according to `rules`
+    pub fn rewrite(self, rules: &RewriteRules) -> WebsiteElement {
+        let url = rules.rewrite_url(self.url);
+        let guid = if self.guid.is_perma_link { Guid::permalink(&url) } else { self.guid };
+
+        WebsiteElement {
+            title: rules.rewrite_title(&self.title),
+            url,
+            guid,
+            ..self
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use scraper::Selector;
+    use crate::html_selector::HtmlSelector;
     use indoc::indoc;
-    use chrono::{TimeZone, NaiveDate};
+    use chrono::Utc;
+    use crate::guid::GuidStrategy;
+    use crate::rate_limit::HostLimits;
+    use crate::feed_request::{FeedRequestBuilder, SourceKind};
 
     /// When parsing items from HTML we need to deal with two types of links:
     ///
@@ -91,16 +515,29 @@ mod tests {
     /// Our RSS output needs all links to be absolute, so we want to test that the rss scraping
     /// correctly transforms relative links into absolute links.
     #[test]
+    #[allow(clippy::zero_prefixed_literal, clippy::get_first)]
     pub fn parse_relative_links() {
         let request = FeedRequest {
             name: "Parse Relative RSS Links Test".into(),
             url: Url::parse("https://example.com/feed/").unwrap(),
-            item_selector: Selector::parse(".item").unwrap(),
-            title_selector: None,
-            link_selector: None,
-            pub_date_selector: None,
+            source: FeedSource::Html(HtmlSelectors {
+                item_selector: ".item".parse::<HtmlSelector>().unwrap(),
+                title_selector: None,
+                link_selector: None,
+                pub_date_selector: None,
+                description_selector: None,
+                image_selector: None,
+                enclosure_selector: None,
+                author_selector: None,
+                category_selector: None,
+            }),
             order: FeedOrder::Normal,
             max_items: 30,
+            rewrite: RewriteRules::default(),
+            guid_strategy: GuidStrategy::Url,
+            channel: ChannelMetadata::default(),
+            host_limits: HostLimits::default(),
+            encoding: None,
         };
 
         let html_body = indoc! {r#"
@@ -113,22 +550,56 @@ mod tests {
         "#};
 
         let now = Local.ymd(2021, 02, 01).and_hms(13, 0, 0);
-        let feed = Website::scrape(&request, html_body, now);
+        let feed = Website::scrape(&request, html_body, now).unwrap();
 
         assert_eq!(feed.elements.get(0).map(|i| i.url.to_string()), Some("https://example.com/feed/item-1".to_string()))
     }
 
     #[test]
+    pub fn scrape_should_fill_in_the_channel_from_the_page() {
+        let request = FeedRequestBuilder::new("Channel Test", Url::parse("https://example.com/feed/").unwrap(), ".item")
+            .feed_description("Everything new")
+            .build()
+            .unwrap();
+
+        let html_body = indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-US">
+            <head><meta name="description" content="The page description"></head>
+            <body><a class="item" href="item-1">Item 1</a></body>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, html_body, now).unwrap();
+
+        assert_eq!(website.channel.description, Some("Everything new".to_string()));
+        assert_eq!(website.channel.language, Some("en-US".to_string()));
+    }
+
+    #[test]
+    #[allow(clippy::zero_prefixed_literal, clippy::get_first)]
     pub fn parse_human_dates() {
         let request = FeedRequest {
             name: "Parse Human Dates Test".into(),
             url: Url::parse("https://example.com/feed/").unwrap(),
-            item_selector: Selector::parse(".item").unwrap(),
-            title_selector: Selector::parse(".link").ok(),
-            link_selector: Selector::parse(".link").ok(),
-            pub_date_selector: Selector::parse(".published").ok(),
+            source: FeedSource::Html(HtmlSelectors {
+                item_selector: ".item".parse::<HtmlSelector>().unwrap(),
+                title_selector: ".link".parse::<HtmlSelector>().ok(),
+                link_selector: ".link".parse::<HtmlSelector>().ok(),
+                pub_date_selector: ".published".parse::<HtmlSelector>().ok(),
+                description_selector: None,
+                image_selector: None,
+                enclosure_selector: None,
+                author_selector: None,
+                category_selector: None,
+            }),
             order: FeedOrder::Normal,
             max_items: 30,
+            rewrite: RewriteRules::default(),
+            guid_strategy: GuidStrategy::Url,
+            channel: ChannelMetadata::default(),
+            host_limits: HostLimits::default(),
+            encoding: None,
         };
 
         let html_body = indoc! {r#"
@@ -143,11 +614,265 @@ mod tests {
         "#};
 
         let now = Local.ymd(2021, 02, 01).and_hms(13, 0, 0);
-        let feed = Website::scrape(&request, html_body, now);
+        let feed = Website::scrape(&request, html_body, now).unwrap();
 
         assert_eq!(
             feed.elements.get(0).and_then(|i| i.pub_date),
             Local.from_local_date(&NaiveDate::from_ymd(2021, 01, 10)).and_hms_opt(0, 0, 0).earliest()
         );
     }
+
+    #[test]
+    pub fn scrape_should_dedupe_items_by_normalized_url() {
+        let request = FeedRequest {
+            name: "Dedupe Items Test".into(),
+            url: Url::parse("https://example.com/feed/").unwrap(),
+            source: FeedSource::Html(HtmlSelectors {
+                item_selector: ".item".parse::<HtmlSelector>().unwrap(),
+                title_selector: None,
+                link_selector: None,
+                pub_date_selector: None,
+                description_selector: None,
+                image_selector: None,
+                enclosure_selector: None,
+                author_selector: None,
+                category_selector: None,
+            }),
+            order: FeedOrder::Normal,
+            max_items: 2,
+            rewrite: RewriteRules::default(),
+            guid_strategy: GuidStrategy::Attribute("data-id".into()),
+            channel: ChannelMetadata::default(),
+            host_limits: HostLimits::default(),
+            encoding: None,
+        };
+
+        let html_body = indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-US">
+            <body>
+                <a class="item" data-id="1" href="item-1/">Pinned: Item 1</a>
+                <a class="item" data-id="1" href="item-1#comments">Item 1</a>
+                <a class="item" href="item-2">Item 2</a>
+            </body>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let feed = Website::scrape(&request, html_body, now).unwrap();
+
+        let guids: Vec<Guid> = feed.elements.into_iter().map(|i| i.guid).collect();
+        assert_eq!(guids, vec![
+            Guid::opaque("1"),
+            Guid::permalink(&Url::parse("https://example.com/feed/item-2").unwrap()),
+        ]);
+    }
+
+    #[test]
+    pub fn scrape_html_authors_and_categories() {
+        let request = FeedRequestBuilder::new("Authors Test", Url::parse("https://example.com/blog/").unwrap(), "article")
+            .link_selector("h2 a")
+            .author_selector(".byline")
+            .category_selector(".tags a")
+            .build()
+            .unwrap();
+
+        let html_body = indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-US">
+            <body>
+              <article>
+                <h2><a href="/the-story">The Story</a></h2>
+                <span class="byline"> Jane Doe </span>
+                <ul class="tags"><li><a>rust</a></li><li><a>news</a></li><li><a>rust</a></li></ul>
+              </article>
+            </body>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, html_body, now).unwrap();
+
+        let element = &website.elements[0];
+        assert_eq!(element.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(element.categories, vec!["rust".to_string(), "news".to_string()]);
+    }
+
+    #[test]
+    pub fn scrape_html_images_and_enclosures() {
+        let request = FeedRequestBuilder::new("Media Test", Url::parse("https://example.com/podcast/").unwrap(), "article")
+            .link_selector("h2 a")
+            .image_selector("img")
+            .enclosure_selector("audio")
+            .build()
+            .unwrap();
+
+        let html_body = indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-US">
+            <body>
+              <article>
+                <h2><a href="/episode-1">Episode 1</a></h2>
+                <img src="/small.jpg" srcset="https://cdn.example.com/c_fill,w_480/cover.jpg 480w, https://cdn.example.com/c_fill,w_1080/cover.jpg 1080w">
+                <audio controls><source src="/episode-1.mp3" type="audio/mpeg"></audio>
+              </article>
+              <article>
+                <h2><a href="/episode-2">Episode 2</a></h2>
+              </article>
+            </body>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, html_body, now).unwrap();
+
+        let element = &website.elements[0];
+        assert_eq!(element.image, Some(Url::parse("https://cdn.example.com/c_fill,w_1080/cover.jpg").unwrap()));
+        assert_eq!(element.enclosure, Some(Enclosure {
+            url: Url::parse("https://example.com/episode-1.mp3").unwrap(),
+            mime_type: Some("audio/mpeg".into()),
+            length: None,
+        }));
+
+        assert_eq!(website.elements[1].image, None);
+        assert_eq!(website.elements[1].enclosure, None);
+    }
+
+    #[test]
+    pub fn scrape_json_items() {
+        let request = FeedRequestBuilder::new("JSON Test", Url::parse("https://example.com/api/posts").unwrap(), "$.data.posts[*]")
+            .source(SourceKind::Json)
+            .title_selector("title")
+            .link_selector("links.html")
+            .pub_date_selector("published_at")
+            .description_selector("$.summary")
+            .guid_strategy(GuidStrategy::Attribute("id".into()))
+            .build()
+            .unwrap();
+
+        let json_body = indoc! {r#"
+            {
+              "data": {
+                "posts": [
+                  {
+                    "id": 42,
+                    "title": "The Story",
+                    "links": { "html": "/posts/the-story" },
+                    "published_at": "2021-01-10T12:00:00Z",
+                    "summary": "A story about <em>things</em>"
+                  },
+                  { "title": "No Link" },
+                  {
+                    "id": "the-other-story",
+                    "title": "The Other Story",
+                    "links": { "html": "https://example.com/posts/the-other-story" },
+                    "published_at": 1609459200
+                  }
+                ]
+              }
+            }
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, json_body, now).unwrap();
+
+        assert_eq!(website.elements.len(), 2);
+
+        let first = &website.elements[0];
+        assert_eq!(first.title, "The Story");
+        assert_eq!(first.url.as_str(), "https://example.com/posts/the-story");
+        assert_eq!(first.pub_date, Some(Utc.ymd(2021, 1, 10).and_hms(12, 0, 0).with_timezone(&Local)));
+        assert_eq!(first.description.as_deref(), Some("A story about <em>things</em>"));
+        assert_eq!(first.guid, Guid::opaque("42"));
+
+        let second = &website.elements[1];
+        assert_eq!(second.guid, Guid::opaque("the-other-story"));
+        assert_eq!(second.pub_date, Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0).with_timezone(&Local)));
+    }
+
+    #[test]
+    pub fn parse_pub_date_should_not_read_digits_as_timestamps() {
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+
+        assert_eq!(parse_pub_date("1609459200", now), None);
+        assert_eq!(timestamp_pub_date(1609459200000), Some(Utc.ymd(2021, 1, 1).and_hms(0, 0, 0).with_timezone(&Local)));
+    }
+
+    #[test]
+    pub fn scrape_should_prefer_exact_dates_from_structured_data() {
+        let request = FeedRequestBuilder::new("Structured Data Test", Url::parse("https://example.com/blog/").unwrap(), ".post")
+            .title_selector("a")
+            .link_selector("a")
+            .pub_date_selector(".published")
+            .build()
+            .unwrap();
+
+        let html_body = indoc! {r#"
+            <!DOCTYPE html>
+            <html lang="en-US">
+            <head>
+              <script type="application/ld+json">
+                { "@type": "BlogPosting", "url": "https://example.com/blog/the-story", "datePublished": "2021-01-10T12:00:00Z" }
+              </script>
+            </head>
+            <body>
+              <div class="post"><a href="the-story">The Story</a><p class="published">3 weeks ago</p></div>
+            </body>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, html_body, now).unwrap();
+
+        assert_eq!(
+            website.elements.first().and_then(|i| i.pub_date),
+            Some(Utc.ymd(2021, 1, 10).and_hms(12, 0, 0).with_timezone(&Local))
+        );
+    }
+
+    #[test]
+    pub fn scrape_feed_items_should_clean_up_an_upstream_feed() {
+        let request = FeedRequestBuilder::new("Feed Test", Url::parse("https://example.com/rss.xml").unwrap(), "")
+            .source(SourceKind::Feed)
+            .strip_query_param("utm_*")
+            .guid_strategy(GuidStrategy::Attribute("guid".into()))
+            .build()
+            .unwrap();
+
+        let rss_body = indoc! {r#"
+            <?xml version="1.0"?>
+            <rss version="2.0">
+            <channel>
+              <item>
+                <title>The Story</title><link>/the-story?utm_source=rss</link><guid isPermaLink="false">story-1</guid>
+                <enclosure url="/story-1.mp3" length="1234" type="audio/x-mpeg"/>
+                <thumbnail url="/story-1.jpg"/>
+              </item>
+              <item><title>The Story Again</title><link>/the-story</link><guid>story-1</guid></item>
+              <item><title>Another Story</title><link>/another-story</link><guid>https://example.com/another-story</guid></item>
+              <item><title>No Link</title></item>
+            </channel>
+            </rss>
+        "#};
+
+        let now = Local.ymd(2021, 2, 1).and_hms(13, 0, 0);
+        let website = Website::scrape(&request, rss_body, now).unwrap();
+
+        assert_eq!(website.elements.len(), 2);
+        assert_eq!(website.elements[0].url.as_str(), "https://example.com/the-story");
+        assert_eq!(website.elements[0].guid, Guid::opaque("story-1"));
+        assert_eq!(website.elements[0].image, Some(Url::parse("https://example.com/story-1.jpg").unwrap()));
+        assert_eq!(website.elements[0].enclosure, Some(Enclosure {
+            url: Url::parse("https://example.com/story-1.mp3").unwrap(),
+            mime_type: Some("audio/x-mpeg".into()),
+            length: Some(1234),
+        }));
+        assert_eq!(website.elements[1].guid, Guid::permalink(&Url::parse("https://example.com/another-story").unwrap()));
+    }
+
+    #[test]
+    pub fn feed_sources_should_only_take_guids_from_the_upstream_guid() {
+        let request = FeedRequestBuilder::new("Feed Test", Url::parse("https://example.com/rss.xml").unwrap(), "")
+            .source(SourceKind::Feed)
+            .guid_strategy(GuidStrategy::Attribute("data-id".into()))
+            .build();
+
+        assert!(request.is_err());
+    }
 }
@@ -1,23 +1,55 @@
+use chrono::{Local, TimeZone};
 use clap::Clap;
+use futures::future;
 use reqwest::Url;
-use tokio;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use mk_rss::{self, FeedRequestBuilder, FeedOrder};
+use mk_rss::{self, AggregateRequest, Archive, Digest, Feed, FeedRequest, FeedRequestBuilder, FeedOrder, GuidStrategy, OpmlOutline, SeenItems, SiteEntry, SmtpConfig, SmtpTls, SourceKind, SourceTag, StaticSite, Webhook, WebhookFormat};
 
 #[derive(Clap, Debug)]
 #[clap(version = "1.0.1", author = "Jake Woods <jake@jakewoods.net>")]
 struct Args {
-    /// The name of this feed
+    /// The name of this feed.
+    ///
+    /// Required by every subcommand that works with a single feed.
+    #[clap(long)]
+    name: Option<String>,
+
+    /// The URL of the page to scrape for this feed.
+    ///
+    /// Required by every subcommand that works with a single feed.
+    #[clap(long)]
+    url: Option<Url>,
+
+    /// The kind of document at `--url`.
+    ///
+    /// "html" scrapes a web page using css selectors.
+    /// "json" scrapes a JSON API, every selector is then a JSONPath (e.g. `$.data.posts[*]`) instead
+    /// of a css selector, and every selector other than `--item-selector` is relative to the item.
+    /// "structured-data" reads the schema.org JSON-LD and microdata embedded in a web page and doesn't need any selectors.
+    /// "sitemap" reads the pages listed in a sitemap.xml (or sitemap index), optionally only those matching `--path-pattern`.
+    /// "feed" reads an existing RSS or Atom feed so it can be rewritten, deduped and trimmed like any other source.
+    #[clap(long, default_value = "html")]
+    source: SourceKind,
+
+    /// When `--source` is "sitemap", fetch every page to find its title rather than guessing it from the url.
     #[clap(long)]
-    name: String,
+    fetch_titles: bool,
 
-    /// The URL of the page to scrape for this feed
+    /// When `--source` is "sitemap", a regex that the path of each page must match to become an item, e.g. `^/blog/`.
     #[clap(long)]
-    url: Url,
+    path_pattern: Option<String>,
 
     /// A jQuery style css selector targeting the HTML nodes that represent a single item in the feed
+    ///
+    /// Any html selector can instead be an XPath expression by prefixing it with `xpath:`,
+    /// e.g. `xpath://h3[contains(., 'Release')]/following-sibling::a[1]`
+    ///
+    /// Required unless `--source` is "structured-data", "sitemap" or "feed".
     #[clap(long)]
-    item_selector: String,
+    item_selector: Option<String>,
 
     /// A jQuery style css selector targeting the HTML node that contains the title.
     ///
@@ -39,6 +71,43 @@ struct Args {
     #[clap(long)]
     pub_date_selector: Option<String>,
 
+    /// A jQuery style css selector indicating the HTML node that contains the items description.
+    ///
+    /// The inner HTML of this node is used as the description.
+    ///
+    /// This selector searches within the node indicated by `--item-selector`.
+    #[clap(long)]
+    description_selector: Option<String>,
+
+    /// A jQuery style css selector indicating the HTML node that contains the items thumbnail.
+    ///
+    /// The url is read from the nodes `srcset` (picking the largest image), `src`, `href` or `content`.
+    ///
+    /// This selector searches within the node indicated by `--item-selector`.
+    #[clap(long)]
+    image_selector: Option<String>,
+
+    /// A jQuery style css selector indicating the HTML node that links to the items audio or video,
+    /// e.g. a podcast episode. It becomes the items `<enclosure>`.
+    ///
+    /// This selector searches within the node indicated by `--item-selector`.
+    #[clap(long)]
+    enclosure_selector: Option<String>,
+
+    /// A jQuery style css selector indicating the HTML node that contains the items author.
+    ///
+    /// This selector searches within the node indicated by `--item-selector`.
+    #[clap(long)]
+    author_selector: Option<String>,
+
+    /// A jQuery style css selector indicating the HTML nodes that contain the items categories (or tags).
+    ///
+    /// Every matching node becomes a `<category>`.
+    ///
+    /// This selector searches within the node indicated by `--item-selector`.
+    #[clap(long)]
+    category_selector: Option<String>,
+
     /// The order of items to return.
     ///
     /// "normal" returns the items in the order they appear on the page from top to bottom.
@@ -50,6 +119,78 @@ struct Args {
     #[clap(long, default_value = "30")]
     max_items: usize,
 
+    /// A `PATTERN=>REPLACEMENT` regex rewrite applied to each items title, e.g. `Read more »=>`
+    ///
+    /// May be given multiple times, rewrites are applied in order.
+    #[clap(long, number_of_values = 1)]
+    title_rewrite: Vec<String>,
+
+    /// A query parameter to remove from each items url, e.g. `sid`.
+    ///
+    /// A trailing `*` removes every parameter with that prefix, e.g. `utm_*`. May be given multiple times.
+    #[clap(long, number_of_values = 1)]
+    strip_query_param: Vec<String>,
+
+    /// A `FROM_HOST=>TO_HOST` rewrite applied to each items url, e.g. `example.com=>m.example.com`
+    ///
+    /// May be given multiple times.
+    #[clap(long, number_of_values = 1)]
+    host_rewrite: Vec<String>,
+
+    /// How to generate each items `<guid>`.
+    ///
+    /// "url" uses the items url, "hash" uses a hash of the items url and title and
+    /// "attribute:NAME" uses the value of the attribute NAME on the item (e.g. "attribute:data-id").
+    /// When `--source` is "feed", only "attribute:guid" is allowed, which keeps the upstream feeds guid
+    #[clap(long, default_value = "url")]
+    guid_strategy: GuidStrategy,
+
+    /// A description of the feed as a whole.
+    ///
+    /// Defaults to the pages meta description or title.
+    #[clap(long)]
+    feed_description: Option<String>,
+
+    /// The language of the feed, e.g. `en-us`.
+    ///
+    /// Defaults to the `lang` of the pages `<html>`.
+    #[clap(long)]
+    language: Option<String>,
+
+    /// The url of an image representing the feed, which may be relative to `--url`.
+    ///
+    /// Defaults to the pages `og:image` or icon.
+    #[clap(long)]
+    image: Option<String>,
+
+    /// How many minutes readers should cache the feed for.
+    #[clap(long)]
+    ttl: Option<u32>,
+
+    /// How many requests may be made to the site at once while fetching this feed.
+    ///
+    /// Defaults to `MK_RSS_HOST_MAX_CONCURRENT_REQUESTS` or 2. The limit is shared by every feed fetching from the same host.
+    #[clap(long)]
+    max_concurrent_requests: Option<usize>,
+
+    /// How many milliseconds to wait between requests to the site while fetching this feed.
+    ///
+    /// Defaults to `MK_RSS_HOST_REQUEST_INTERVAL_MS` or 250.
+    #[clap(long)]
+    request_interval_ms: Option<u64>,
+
+    /// Whether to check the sites robots.txt before fetching this feed, "true" or "false".
+    ///
+    /// Defaults to `MK_RSS_RESPECT_ROBOTS_TXT`. Rules are looked up for the user agent `MK_RSS_ROBOTS_USER_AGENT` or "mk-rss".
+    #[clap(long)]
+    respect_robots_txt: Option<bool>,
+
+    /// The encoding of the page, e.g. `shift_jis` or `windows-1251`.
+    ///
+    /// Defaults to the encoding given by the server or declared by the page, or UTF-8.
+    #[clap(long)]
+    encoding: Option<String>,
+
     #[clap(subcommand)]
     command: Command
 }
@@ -58,11 +199,162 @@ struct Args {
 enum Command {
     /// Fetch the indicated feed and return the generate RSS XML on standard output.
     #[clap()]
-    Fetch,
+    Fetch(FetchArgs),
 
     /// Convert the arguments of this command into URL parameters suitable for querying the lambda endpoint of mk-rss
     #[clap()]
-    ToRSSUrl(ToRSSUrl)
+    ToRSSUrl(ToRSSUrl),
+
+    /// Print the `mk-rss-cli` invocation equivalent to an mk-rss lambda URL, the reverse of `to-rss-url`.
+    ///
+    /// Every top-level option is ignored.
+    #[clap()]
+    FromRSSUrl(FromRSSUrl),
+
+    /// Fetch several feeds concurrently and merge them into a single feed, newest items first.
+    ///
+    /// `--name` and `--url` name the merged feed, every other top-level option is ignored.
+    #[clap()]
+    Aggregate(Aggregate),
+
+    /// List the RSS and Atom feeds the page at `--url` already provides, with how many items each has.
+    ///
+    /// Checks the feeds advertised in the pages `<head>` as well as common feed paths like `/feed` and `/rss.xml`.
+    /// Any of these can be used with `--source feed` instead of scraping the page with selectors.
+    #[clap()]
+    Discover,
+
+    /// Periodically fetch a list of feeds and POST any new items to a webhook.
+    ///
+    /// The guids of the items we've already seen are kept in `--state` between runs. The first time
+    /// a feed is checked its current items are only recorded, not posted. Every top-level option is ignored.
+    #[clap()]
+    Watch(Watch),
+
+    /// Email a digest of the items that are new in a list of feeds since the last run.
+    ///
+    /// The guids of the items we've already sent are kept in `--state` between runs. The first time
+    /// a feed is checked its current items are only recorded, not sent. Every top-level option is ignored.
+    #[clap()]
+    Digest(DigestArgs),
+
+    /// List the items kept in an archive written by `fetch --archive`, newest first.
+    ///
+    /// Every top-level option is ignored.
+    #[clap()]
+    History(History),
+
+    /// Fetch every feed listed in a manifest and write them to a directory as static files, for hosting without the lambda.
+    ///
+    /// Each feed is written as RSS (`<name>.xml`), Atom (`<name>.atom`) and JSON Feed (`<name>.json`), along with
+    /// an `index.html` listing them and a `feeds.opml` to subscribe to them all. Files whose content hasn't changed
+    /// aren't rewritten, so it can be run from cron and synced to object storage. Every top-level option is ignored.
+    #[clap()]
+    Build(Build),
+
+    /// Write an OPML subscription list of feed definitions, each served by the mk-rss lambda, for importing into a reader.
+    ///
+    /// Every top-level option is ignored.
+    #[clap()]
+    ExportOpml(ExportOpml),
+
+    /// Check every subscription in an OPML file (e.g. exported from a reader) and report the ones that are broken.
+    ///
+    /// Broken subscriptions are feeds that can't be fetched, aren't RSS or Atom, or have no items. They are good
+    /// candidates for a scraper definition. Every top-level option is ignored.
+    #[clap()]
+    ImportOpml(ImportOpml)
+}
+
+#[derive(Clap, Debug)]
+struct ExportOpml {
+    /// A feed to export, as a URL produced by `to-rss-url` or just its query string.
+    ///
+    /// May be given multiple times.
+    #[clap(long, number_of_values = 1)]
+    feed: Vec<String>,
+
+    /// A file listing feeds to export, one per line in the same format as `--feed`.
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    #[clap(long)]
+    feed_file: Option<PathBuf>,
+
+    /// The URL currently hosting the mk-rss lambda.
+    #[clap(long)]
+    lambda_url: Url,
+
+    /// The title of the subscription list.
+    #[clap(long, default_value = "mk-rss")]
+    title: String,
+}
+
+#[derive(Clap, Debug)]
+struct ImportOpml {
+    /// The OPML file to check.
+    #[clap(long)]
+    opml: PathBuf,
+
+    /// Write a starting definition for each broken subscription to this file, to be finished with selectors
+    /// and used as a `--feed-file`. Each definition is commented out until it's finished.
+    #[clap(long)]
+    definitions: Option<PathBuf>,
+}
+
+#[derive(Clap, Debug)]
+struct Build {
+    /// A file listing the feeds to build, one per line as a URL produced by `to-rss-url` or just its query string.
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    #[clap(long)]
+    manifest: PathBuf,
+
+    /// The directory to write the site to, created if it doesn't exist.
+    #[clap(long)]
+    output: PathBuf,
+
+    /// The URL `--output` will be served from, so feeds can link to themselves.
+    ///
+    /// Without it the OPML file lists each feed by a relative URL.
+    #[clap(long)]
+    base_url: Option<Url>,
+
+    /// The title of the index page and OPML file.
+    #[clap(long, default_value = "Feeds")]
+    title: String,
+}
+
+#[derive(Clap, Debug)]
+struct FetchArgs {
+    /// An SQLite database that keeps every item this feed has ever had, created if it doesn't exist.
+    ///
+    /// Items that have dropped off the page are added back to the feed from the archive, up to `--archive-items`.
+    /// Feeds are archived by all of their options, so changing any of them starts a new history.
+    #[clap(long)]
+    archive: Option<PathBuf>,
+
+    /// The maximum number of items to return when `--archive` is given, including those still on the page.
+    #[clap(long, default_value = "100")]
+    archive_items: usize,
+}
+
+#[derive(Clap, Debug)]
+struct History {
+    /// The archive to read, as written by `fetch --archive`.
+    #[clap(long)]
+    archive: PathBuf,
+
+    /// Only list items from the feed with this name.
+    #[clap(long)]
+    feed: Option<String>,
+
+    /// Only list items published since this date, e.g. `2021-01-31` or `last week`.
+    #[clap(long)]
+    since: Option<String>,
+
+    /// Only list items whose title contains this text, ignoring case.
+    #[clap(long)]
+    search: Option<String>,
 }
 
 #[derive(Clap, Debug)]
@@ -70,6 +362,141 @@ struct ToRSSUrl {
     /// The URL currently hosting the mk-rss lambda.
     #[clap(long)]
     lambda_url: Url,
+
+    /// Encode the feed as a compact signed token served at `<lambda-url>/f/<token>`, rather than as a query string.
+    ///
+    /// The lambda must have the same `--token-secret` in its `MK_RSS_TOKEN_SECRET` environment variable.
+    #[clap(long)]
+    token: bool,
+
+    /// The secret to sign the token with.
+    ///
+    /// Defaults to the `MK_RSS_TOKEN_SECRET` environment variable, which keeps it out of your shell history.
+    #[clap(long)]
+    token_secret: Option<String>,
+}
+
+#[derive(Clap, Debug)]
+struct FromRSSUrl {
+    /// The mk-rss lambda URL, or just its query string.
+    rss_url: String,
+}
+
+#[derive(Clap, Debug)]
+struct Aggregate {
+    /// A feed to merge, as a URL produced by `to-rss-url` or just its query string.
+    ///
+    /// May be given multiple times.
+    #[clap(long, number_of_values = 1, required = true)]
+    feed: Vec<String>,
+
+    /// How each item records which feed it came from.
+    ///
+    /// "category" adds a `<category>` named after the feed, "source" adds an RSS `<source>` element.
+    #[clap(long, default_value = "category")]
+    source_tag: SourceTag,
+
+    /// The maximum number of items to return across all feeds.
+    #[clap(long, default_value = "30")]
+    max_items: usize,
+
+    /// Instead of fetching the merged feed, print the URL that fetches it from the mk-rss lambda hosted here.
+    #[clap(long)]
+    lambda_url: Option<Url>,
+}
+
+#[derive(Clap, Debug)]
+struct Watch {
+    /// A feed to watch, as a URL produced by `to-rss-url` or just its query string.
+    ///
+    /// May be given multiple times.
+    #[clap(long, number_of_values = 1)]
+    feed: Vec<String>,
+
+    /// A file listing feeds to watch, one per line in the same format as `--feed`.
+    ///
+    /// Blank lines and lines starting with `#` are ignored. The file is read again before every check.
+    #[clap(long)]
+    feed_file: Option<PathBuf>,
+
+    /// The URL to POST new items to.
+    #[clap(long)]
+    webhook_url: Url,
+
+    /// The shape of the JSON posted to `--webhook-url`.
+    ///
+    /// "json" posts the feed and every new item, "slack" and "discord" post a message for a Slack or Discord webhook.
+    #[clap(long, default_value = "json")]
+    webhook_format: WebhookFormat,
+
+    /// How many times to retry a failed POST before giving up until the next check.
+    #[clap(long, default_value = "3")]
+    retries: u32,
+
+    /// Where to keep the guids of the items we've already seen.
+    #[clap(long, default_value = "mk-rss-seen.json")]
+    state: PathBuf,
+
+    /// How many minutes to wait between checks.
+    #[clap(long, default_value = "30")]
+    interval: u64,
+
+    /// Check once and exit rather than checking every `--interval` minutes, e.g. when run from cron.
+    #[clap(long)]
+    once: bool,
+}
+
+#[derive(Clap, Debug)]
+struct DigestArgs {
+    /// A feed to include, as a URL produced by `to-rss-url` or just its query string.
+    ///
+    /// May be given multiple times.
+    #[clap(long, number_of_values = 1)]
+    feed: Vec<String>,
+
+    /// A file listing feeds to include, one per line in the same format as `--feed`.
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    #[clap(long)]
+    feed_file: Option<PathBuf>,
+
+    /// Where to keep the guids of the items we've already sent.
+    #[clap(long, default_value = "mk-rss-digest-seen.json")]
+    state: PathBuf,
+
+    /// The address to send the digest from, e.g. `mk-rss <mk-rss@example.com>`
+    #[clap(long)]
+    from: String,
+
+    /// An address to send the digest to. May be given multiple times.
+    #[clap(long, number_of_values = 1, required = true)]
+    to: Vec<String>,
+
+    /// The SMTP server to send the digest through.
+    #[clap(long)]
+    smtp_host: String,
+
+    /// The port of `--smtp-host`, defaults to 587 for "starttls", 465 for "tls" and 25 for "none".
+    #[clap(long)]
+    smtp_port: Option<u16>,
+
+    /// How to secure the connection to `--smtp-host`: "starttls", "tls" or "none".
+    #[clap(long, default_value = "starttls")]
+    smtp_tls: SmtpTls,
+
+    /// The username to log in to `--smtp-host` with.
+    #[clap(long)]
+    smtp_username: Option<String>,
+
+    /// The password to log in to `--smtp-host` with.
+    ///
+    /// Defaults to the `MK_RSS_SMTP_PASSWORD` environment variable, which keeps it out of your shell history.
+    #[clap(long)]
+    smtp_password: Option<String>,
+
+    /// Print the digest rather than sending it. Nothing is recorded in `--state`.
+    #[clap(long)]
+    dry_run: bool,
 }
 
 #[tokio::main]
@@ -79,61 +506,440 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
     match args.command {
-        Command::Fetch => fetch(args).await?,
-        Command::ToRSSUrl(ref command_args) => to_rss_url(&args, command_args),
+        Command::Fetch(ref command_args) => fetch(&args, command_args).await?,
+        Command::ToRSSUrl(ref command_args) => to_rss_url(&args, command_args)?,
+        Command::FromRSSUrl(ref command_args) => from_rss_url(command_args)?,
+        Command::Aggregate(ref command_args) => aggregate(&args, command_args).await?,
+        Command::Discover => discover(args).await?,
+        Command::Watch(ref command_args) => watch(command_args).await?,
+        Command::Digest(ref command_args) => digest(command_args).await?,
+        Command::History(ref command_args) => history(command_args)?,
+        Command::Build(ref command_args) => build(command_args).await?,
+        Command::ExportOpml(ref command_args) => export_opml(command_args)?,
+        Command::ImportOpml(ref command_args) => import_opml(command_args).await?,
     };
 
     Ok(())
 }
 
-async fn fetch(args: Args) -> Result<(), Box<dyn std::error::Error>> {
-    let feed_request_builder = FeedRequestBuilder {
-        name: args.name,
-        url: args.url,
-        item_selector: args.item_selector,
-        title_selector: args.title_selector,
-        link_selector: args.link_selector,
-        pub_date_selector: args.pub_date_selector,
-        order: Some(args.order),
-        max_items: Some(args.max_items)
-    };
+impl Args {
+    fn required_name(&self) -> anyhow::Result<String> {
+        self.name.clone().ok_or_else(|| anyhow::anyhow!("--name is required"))
+    }
+
+    fn required_url(&self) -> anyhow::Result<Url> {
+        self.url.clone().ok_or_else(|| anyhow::anyhow!("--url is required"))
+    }
+}
+
+async fn fetch(args: &Args, command_args: &FetchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let builder = feed_request_builder(args)?;
 
-    let feed_request = feed_request_builder.build()?;
+    let mut feed = mk_rss::fetch_feed(builder.build()?).await?;
+
+    if let Some(archive) = &command_args.archive {
+        let key = Archive::key(builder.to_query_pairs());
+        feed = Archive::open(archive)?.extend_feed(&key, feed, command_args.archive_items, Local::now())?;
+    }
 
-    let feed = mk_rss::fetch_feed(feed_request).await?;
     println!("{}", feed.to_rss_xml());
 
     Ok(())
 }
 
-fn to_rss_url(args: &Args, command_args: &ToRSSUrl) {
+/// The feed described by the top-level options
+fn feed_request_builder(args: &Args) -> anyhow::Result<FeedRequestBuilder> {
+    Ok(FeedRequestBuilder {
+        name: args.required_name()?,
+        url: args.required_url()?,
+        item_selector: args.item_selector.clone(),
+        title_selector: args.title_selector.clone(),
+        link_selector: args.link_selector.clone(),
+        pub_date_selector: args.pub_date_selector.clone(),
+        description_selector: args.description_selector.clone(),
+        image_selector: args.image_selector.clone(),
+        enclosure_selector: args.enclosure_selector.clone(),
+        author_selector: args.author_selector.clone(),
+        category_selector: args.category_selector.clone(),
+        source: Some(args.source),
+        fetch_titles: Some(args.fetch_titles),
+        path_pattern: args.path_pattern.clone(),
+        order: Some(args.order),
+        max_items: Some(args.max_items),
+        title_rewrites: args.title_rewrite.clone(),
+        strip_query_params: args.strip_query_param.clone(),
+        host_rewrites: args.host_rewrite.clone(),
+        guid_strategy: Some(args.guid_strategy.clone()),
+        feed_description: args.feed_description.clone(),
+        language: args.language.clone(),
+        image: args.image.clone(),
+        ttl: args.ttl,
+        max_concurrent_requests: args.max_concurrent_requests,
+        request_interval_ms: args.request_interval_ms,
+        respect_robots_txt: args.respect_robots_txt,
+        encoding: args.encoding.clone(),
+    })
+}
+
+fn to_rss_url(args: &Args, command_args: &ToRSSUrl) -> anyhow::Result<()> {
+    let builder = feed_request_builder(args)?;
     let mut rss_url = command_args.lambda_url.clone();
 
-    rss_url
-        .query_pairs_mut()
-        .append_pair("name", &args.name)
-        .append_pair("url", &args.url.as_str())
-        .append_pair("item_selector", &args.item_selector);
+    if command_args.token {
+        let secret = command_args.token_secret
+            .clone()
+            .or_else(|| std::env::var("MK_RSS_TOKEN_SECRET").ok())
+            .ok_or_else(|| anyhow::anyhow!("--token requires --token-secret or MK_RSS_TOKEN_SECRET"))?;
+
+        // A token nobody can decode is no use, so check it describes a valid feed first
+        builder.build()?;
+
+        let path = format!("{}/f/{}", rss_url.path().trim_end_matches('/'), builder.to_token(secret.as_bytes()));
+        rss_url.set_path(&path);
+    } else {
+        rss_url.query_pairs_mut().extend_pairs(builder.to_query_pairs());
+    }
+
+    println!("{}", rss_url);
+    Ok(())
+}
+
+fn from_rss_url(command_args: &FromRSSUrl) -> anyhow::Result<()> {
+    let builder = FeedRequestBuilder::from_definition(&command_args.rss_url)?;
+
+    // The same checks the lambda makes, so we never print an invocation that can't work
+    builder.build()?;
+
+    println!("mk-rss-cli {} fetch", builder.to_cli_command());
+    Ok(())
+}
+
+async fn aggregate(args: &Args, command_args: &Aggregate) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(lambda_url) = &command_args.lambda_url {
+        let mut rss_url = lambda_url.clone();
+
+        rss_url
+            .query_pairs_mut()
+            .append_pair("name", &args.required_name()?)
+            .append_pair("url", args.required_url()?.as_str())
+            .append_pair("source_tag", &command_args.source_tag.to_string())
+            .append_pair("max_items", &command_args.max_items.to_string());
+
+        for feed in &command_args.feed {
+            rss_url.query_pairs_mut()
+                   .append_pair("feed", feed);
+        }
 
-    if let Some(title_selector) = &args.title_selector {
-        rss_url.query_pairs_mut()
-               .append_pair("title_selector", title_selector);
+        println!("{}", rss_url);
+        return Ok(());
     }
 
-    if let Some(link_selector) = &args.link_selector {
-        rss_url.query_pairs_mut()
-               .append_pair("link_selector", link_selector);
+    let feeds = command_args.feed
+        .iter()
+        .map(|definition| FeedRequestBuilder::from_definition(definition).and_then(|builder| builder.build()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut aggregate_request = AggregateRequest::new(&args.required_name()?, args.required_url()?, feeds);
+    aggregate_request.source_tag = command_args.source_tag;
+    aggregate_request.max_items = command_args.max_items;
+
+    let feed = mk_rss::fetch_aggregate_feed(aggregate_request).await?;
+    println!("{}", feed.to_rss_xml());
+
+    Ok(())
+}
+
+async fn discover(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let url = args.required_url()?;
+    let feeds = mk_rss::discover_feeds(url.clone()).await?;
+
+    if feeds.is_empty() {
+        eprintln!("{} doesn't provide any feeds", url);
     }
 
-    if let Some(pub_date_selector) = &args.pub_date_selector {
-        rss_url.query_pairs_mut()
-               .append_pair("pub_date_selector", pub_date_selector);
+    for feed in feeds {
+        let item_count = match feed.item_count {
+            Some(item_count) => format!("{} items", item_count),
+            None => "unreadable".to_string(),
+        };
+
+        let found = if feed.advertised { "advertised" } else { "common path" };
+
+        match feed.title {
+            Some(title) => println!("{}\t{}\t{}\t{}", feed.url, item_count, found, title),
+            None => println!("{}\t{}\t{}", feed.url, item_count, found),
+        }
     }
 
-    rss_url
-        .query_pairs_mut()
-        .append_pair("order", &args.order.to_string())
-        .append_pair("max_items", &args.max_items.to_string());
+    Ok(())
+}
+
+async fn watch(command_args: &Watch) -> Result<(), Box<dyn std::error::Error>> {
+    let mut webhook = Webhook::new(command_args.webhook_url.clone());
+    webhook.format = command_args.webhook_format;
+    webhook.retries = command_args.retries;
 
-    println!("{}", rss_url);
+    loop {
+        // A bad check (e.g. a typo in the feed file) shouldn't stop us watching, unless we're only checking once.
+        if let Err(e) = check_for_new_items(command_args, &webhook).await {
+            if command_args.once {
+                return Err(e.into());
+            }
+
+            eprintln!("{}", e);
+        }
+
+        if command_args.once {
+            return Ok(());
+        }
+
+        tokio::time::sleep(Duration::from_secs(command_args.interval * 60)).await;
+    }
+}
+
+async fn check_for_new_items(command_args: &Watch, webhook: &Webhook) -> anyhow::Result<()> {
+    let feed_requests = read_feed_requests(&command_args.feed, command_args.feed_file.as_deref())?;
+    let mut seen = SeenItems::load(&command_args.state)?;
+
+    for feed in fetch_feeds(feed_requests).await {
+        if !seen.knows(&feed) {
+            seen.mark_seen(&feed);
+            continue;
+        }
+
+        let new_items = seen.new_items(&feed);
+        if new_items.is_empty() {
+            continue;
+        }
+
+        // Items are only marked as seen once they've been delivered, so a failed POST is retried on the next check.
+        match webhook.notify(&feed, &new_items).await {
+            Ok(()) => seen.mark_seen(&feed),
+            Err(e) => eprintln!("Could not post {} new items from {}: {}", new_items.len(), feed.name, e),
+        }
+    }
+
+    seen.save(&command_args.state)
+}
+
+async fn digest(command_args: &DigestArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let feed_requests = read_feed_requests(&command_args.feed, command_args.feed_file.as_deref())?;
+    let mut seen = SeenItems::load(&command_args.state)?;
+    let feeds = fetch_feeds(feed_requests).await;
+
+    let mut digest = Digest::default();
+    for feed in &feeds {
+        if seen.knows(feed) {
+            digest.add(feed, seen.new_items(feed));
+        }
+    }
+
+    if command_args.dry_run {
+        println!("{}\n\n{}", digest.subject(), digest.to_text());
+        return Ok(());
+    }
+
+    if digest.is_empty() {
+        eprintln!("No new items");
+    } else {
+        let config = SmtpConfig {
+            host: command_args.smtp_host.clone(),
+            port: command_args.smtp_port,
+            tls: command_args.smtp_tls,
+            username: command_args.smtp_username.clone(),
+            password: command_args.smtp_password.clone().or_else(|| std::env::var("MK_RSS_SMTP_PASSWORD").ok()),
+        };
+
+        digest.send(&config, &command_args.from, &command_args.to).await?;
+    }
+
+    // Items are only marked as seen once they've been sent, so a failed send is retried on the next run.
+    for feed in &feeds {
+        seen.mark_seen(feed);
+    }
+
+    seen.save(&command_args.state)?;
+    Ok(())
+}
+
+fn history(command_args: &History) -> Result<(), Box<dyn std::error::Error>> {
+    let archive = Archive::open(&command_args.archive)?;
+    let feed = command_args.feed.as_deref();
+
+    let since = match &command_args.since {
+        Some(since) => mk_rss::parse_pub_date(since, Local::now())
+            .ok_or_else(|| anyhow::anyhow!("Could not parse --since date: {}", since))?,
+        None => Local.timestamp(0, 0),
+    };
+
+    let mut items = match &command_args.search {
+        Some(text) => archive.search(feed, text)?,
+        None => archive.items_since(feed, since)?,
+    };
+
+    items.retain(|archived| archived.item.pub_date >= since);
+
+    for archived in items {
+        println!("{}\t{}\t{}\t{}", archived.item.pub_date.format("%Y-%m-%d"), archived.feed_name, archived.item.title, archived.item.url);
+    }
+
+    Ok(())
+}
+
+async fn build(command_args: &Build) -> Result<(), Box<dyn std::error::Error>> {
+    let feed_requests = read_feed_requests(&[], Some(&command_args.manifest))?;
+    let feed_count = feed_requests.len();
+    let listed: Vec<(String, Url)> = feed_requests.iter().map(|request| (request.name.clone(), request.url.clone())).collect();
+    let feeds = future::join_all(feed_requests.into_iter().map(mk_rss::fetch_feed)).await;
+
+    let entries: Vec<SiteEntry> = listed
+        .into_iter()
+        .zip(feeds)
+        .map(|((name, url), feed)| {
+            let feed = feed.map_err(|e| eprintln!("Could not fetch {}: {}", name, e)).ok();
+            SiteEntry { name, url, feed }
+        })
+        .collect();
+
+    let failed_count = entries.iter().filter(|entry| entry.feed.is_none()).count();
+
+    let mut site = StaticSite::new(&command_args.title, &command_args.output);
+    site.base_url = command_args.base_url.clone();
+
+    let summary = site.build(entries)?;
+
+    for path in &summary.written {
+        eprintln!("Wrote {}", path.display());
+    }
+
+    eprintln!("{} files written, {} unchanged", summary.written.len(), summary.unchanged);
+
+    // The feeds that failed keep their files from the last build, but a cron job should still hear about them.
+    if failed_count > 0 {
+        return Err(anyhow::anyhow!("{} of {} feeds could not be fetched", failed_count, feed_count).into());
+    }
+
+    Ok(())
+}
+
+fn export_opml(command_args: &ExportOpml) -> Result<(), Box<dyn std::error::Error>> {
+    let mut definitions = command_args.feed.clone();
+
+    if let Some(feed_file) = &command_args.feed_file {
+        let text = fs::read_to_string(feed_file)
+            .map_err(|e| anyhow::anyhow!("Could not read {:?}: {}", feed_file, e))?;
+        definitions.extend(text.lines().map(str::to_string));
+    }
+
+    let definitions: Vec<&String> = definitions
+        .iter()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .collect();
+
+    if definitions.is_empty() {
+        return Err(anyhow::anyhow!("At least one --feed or a --feed-file is required").into());
+    }
+
+    let outlines = definitions
+        .into_iter()
+        .map(|definition| {
+            let feed_request = FeedRequestBuilder::from_definition(definition)
+                .and_then(|builder| builder.build())
+                .map_err(|e| anyhow::anyhow!("Could not parse feed {}: {}", definition, e))?;
+
+            let mut rss_url = command_args.lambda_url.clone();
+            rss_url.query_pairs_mut().extend_pairs(mk_rss::definition_query_pairs(definition));
+
+            Ok(OpmlOutline {
+                title: feed_request.name,
+                xml_url: rss_url.to_string(),
+                html_url: Some(feed_request.url.to_string()),
+            })
+        })
+        .collect::<anyhow::Result<Vec<OpmlOutline>>>()?;
+
+    print!("{}", mk_rss::to_opml(&command_args.title, &outlines));
+    Ok(())
+}
+
+async fn import_opml(command_args: &ImportOpml) -> Result<(), Box<dyn std::error::Error>> {
+    let text = fs::read_to_string(&command_args.opml)
+        .map_err(|e| anyhow::anyhow!("Could not read {:?}: {}", command_args.opml, e))?;
+    let outlines = mk_rss::parse_opml(&text)?;
+
+    let checks = future::join_all(outlines.iter().map(|outline| async move {
+        let url = Url::parse(&outline.xml_url).map_err(|e| anyhow::anyhow!("Could not parse URL: {}", e))?;
+        mk_rss::check_feed(url).await
+    })).await;
+
+    let mut definitions = vec![];
+    for (outline, check) in outlines.iter().zip(checks) {
+        let problem = match check {
+            Ok(0) => "no items".to_string(),
+            Ok(item_count) => {
+                println!("ok\t{} items\t{}\t{}", item_count, outline.title, outline.xml_url);
+                continue;
+            },
+            Err(e) => e.to_string(),
+        };
+
+        println!("broken\t{}\t{}\t{}", problem, outline.title, outline.xml_url);
+
+        // The page the feed was about is the best place to start scraping, failing that the site the feed was on
+        let page_url = outline.html_url.clone().or_else(|| {
+            Url::parse(&outline.xml_url).ok().and_then(|url| url.join("/").ok()).map(|url| url.to_string())
+        });
+
+        if let Some(page_url) = page_url {
+            let definition = form_urlencoded::Serializer::new(String::new())
+                .append_pair("name", &outline.title)
+                .append_pair("url", &page_url)
+                .append_pair("item_selector", "")
+                .finish();
+
+            definitions.push(format!("# {} ({}): {}\n# {}\n", outline.title, outline.xml_url, problem, definition));
+        }
+    }
+
+    if let Some(path) = &command_args.definitions {
+        fs::write(path, definitions.join("\n")).map_err(|e| anyhow::anyhow!("Could not write {:?}: {}", path, e))?;
+        eprintln!("Wrote {} starting definitions to {:?}", definitions.len(), path);
+    }
+
+    Ok(())
+}
+
+/// Fetch every feed in `feed_requests` concurrently, reporting (and leaving out) any that fail
+async fn fetch_feeds(feed_requests: Vec<FeedRequest>) -> Vec<Feed> {
+    let names: Vec<String> = feed_requests.iter().map(|request| request.name.clone()).collect();
+    let feeds = future::join_all(feed_requests.into_iter().map(mk_rss::fetch_feed)).await;
+
+    names
+        .into_iter()
+        .zip(feeds)
+        .filter_map(|(name, feed)| match feed {
+            Ok(feed) => Some(feed),
+            Err(e) => {
+                eprintln!("Could not fetch {}: {}", name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The feeds given with `--feed` followed by those listed in `--feed-file`
+fn read_feed_requests(feeds: &[String], feed_file: Option<&Path>) -> anyhow::Result<Vec<FeedRequest>> {
+    let mut feed_requests = mk_rss::parse_feed_definitions(&feeds.join("\n"))?;
+
+    if let Some(feed_file) = feed_file {
+        let definitions = fs::read_to_string(feed_file)
+            .map_err(|e| anyhow::anyhow!("Could not read {:?}: {}", feed_file, e))?;
+        feed_requests.extend(mk_rss::parse_feed_definitions(&definitions)?);
+    }
+
+    if feed_requests.is_empty() {
+        return Err(anyhow::anyhow!("At least one --feed or a --feed-file is required"));
+    }
+
+    Ok(feed_requests)
 }
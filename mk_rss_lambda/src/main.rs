@@ -1,32 +1,132 @@
 use anyhow::{self, Context};
+use chrono::Local;
 use netlify_lambda_http::{IntoResponse, Request, RequestExt, Response};
 use netlify_lambda_http::lambda;
+use std::cmp;
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::env;
+use std::fmt;
 use reqwest::Url;
 
-use mk_rss::{self, FeedRequest, FeedRequestBuilder, FeedOrder};
+use mk_rss::{self, AggregateRequest, Archive, ArchiveLinks, ChannelMetadata, Feed, FeedRequest, FeedRequestBuilder, RobotsDisallowed, SourceTag};
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+/// The environment variable naming the SQLite archive that keeps every item we've served, e.g. on a
+/// mounted EFS volume. Feeds aren't archived or paged without it.
+const ARCHIVE_PATH_VARIABLE: &str = "MK_RSS_ARCHIVE";
+
+/// How many items each archive page holds
+const ARCHIVE_PAGE_SIZE: usize = 30;
+
+/// The most feeds one aggregate request may merge, since each is fetched on every request
+const MAX_AGGREGATE_FEEDS: usize = 10;
+
+/// The environment variable holding the secret feed tokens are signed with. Tokens aren't accepted without it.
+const TOKEN_SECRET_VARIABLE: &str = "MK_RSS_TOKEN_SECRET";
+
+/// When this environment variable is "true" only feeds requested with a signed token are served,
+/// so nobody else can point the lambda at whatever site they like.
+const REQUIRE_TOKEN_VARIABLE: &str = "MK_RSS_REQUIRE_TOKEN";
+
+/// The error returned when a request isn't allowed, as opposed to being malformed
+#[derive(Debug)]
+struct Forbidden(String);
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+/// A feed someone asked the lambda for
+struct Requested {
+    feed: RequestedFeed,
+
+    /// Identifies the feed in the archive by everything that defines it rather than just its name,
+    /// so nobody can add items to another feed's archive by reusing its name
+    archive_key: String,
+
+    /// Whether the feed was requested with a signed token
+    is_signed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum RequestedFeed {
+    Single(Box<FeedRequest>),
+    Aggregate(AggregateRequest),
+}
+
+impl RequestedFeed {
+    async fn fetch(self) -> anyhow::Result<Feed> {
+        match self {
+            RequestedFeed::Single(request) => mk_rss::fetch_feed(*request).await,
+            RequestedFeed::Aggregate(request) => mk_rss::fetch_aggregate_feed(request).await,
+        }
+    }
+
+    /// Make sure the feed doesn't fetch from any site harder than the process defaults allow, or
+    /// ignore robots.txt when we respect it by default, for feeds requested without a token where
+    /// anyone could have chosen the limits
+    fn limit_hosts(&mut self) {
+        match self {
+            RequestedFeed::Single(request) => request.host_limits = request.host_limits.no_looser_than_defaults(),
+            RequestedFeed::Aggregate(request) => {
+                for feed in &mut request.feeds {
+                    feed.host_limits = feed.host_limits.no_looser_than_defaults();
+                }
+            },
+        }
+    }
+
+    /// The feed as it is before fetching anything, for serving pages of its archive
+    fn without_items(self) -> Feed {
+        let (name, url, channel) = match self {
+            RequestedFeed::Single(request) => (request.name, request.url, request.channel),
+            RequestedFeed::Aggregate(request) => (request.name, request.url, ChannelMetadata::default()),
+        };
+
+        Feed {
+            name,
+            url,
+            items: vec![],
+            channel,
+            last_build_date: Local::now(),
+            self_url: None,
+            archive_links: ArchiveLinks::default(),
+        }
+    }
+}
+
 #[lambda::lambda(http)]
 #[tokio::main]
 async fn main(request: Request, _: lambda::Context) -> Result<impl IntoResponse, Error> {
-    let feed_request = make_feed_request(request)?;
-    let response = match mk_rss::fetch_feed(feed_request).await {
+    let response = match respond(request).await {
         Ok(feed) => {
             let xml = feed.to_rss_xml();
 
+            // Archive pages never change, so readers and caches can keep them forever.
+            let cache_control = if feed.archive_links.is_archive { "public, max-age=31536000, immutable" } else { "public, max-age=300" };
+
             Response::builder()
                 .status(200)
                 .header("Content-Type", "application/rss+xml")
+                .header("Cache-Control", cache_control)
                 .body(xml)
                 .expect("failed to render response")
         },
 
         Err(e) => {
+            // Requests we refuse, or for sites that asked not to be scraped, aren't malformed
+            let is_forbidden = e.chain().any(|cause| cause.is::<Forbidden>() || cause.is::<RobotsDisallowed>());
+            let status = if is_forbidden { 403 } else { 400 };
+
             Response::builder()
-                .status(400)
-                .body(format!("{}", e).into())
+                .status(status)
+                .body(format!("{}", e))
                 .expect("failed to render response")
         }
     };
@@ -34,7 +134,165 @@ async fn main(request: Request, _: lambda::Context) -> Result<impl IntoResponse,
     Ok(response)
 }
 
-fn make_feed_request(request: Request) -> anyhow::Result<FeedRequest> {
+async fn respond(request: Request) -> anyhow::Result<Feed> {
+    // Readers use this to find the canonical url of the feed, which is the url they requested.
+    let self_url = Url::parse(&request.uri().to_string()).ok();
+    let archive_page = request.query_string_parameters().get("archive_page").map(str::to_string);
+
+    let requested = make_request(&request)?;
+
+    // Once feeds can be requested with tokens, only those requests may add to the archive. Anyone can still read it.
+    let may_store = requested.is_signed || env::var_os(TOKEN_SECRET_VARIABLE).is_none();
+
+    let archive = match env::var_os(ARCHIVE_PATH_VARIABLE) {
+        Some(archive_path) => Some(Archive::open(archive_path.as_ref())?),
+        None if archive_page.is_some() => return Err(anyhow::anyhow!("archive_page requires {} to be set", ARCHIVE_PATH_VARIABLE)),
+        None => None,
+    };
+
+    // Archive pages come from the archive alone, so serving one doesn't touch the site
+    if let (Some(archive), Some(archive_page)) = (&archive, &archive_page) {
+        let mut feed = requested.feed.without_items();
+        feed.self_url = self_url;
+        return archive_page_feed(feed, archive, &requested.archive_key, archive_page);
+    }
+
+    let mut feed = requested.feed.fetch().await?;
+    feed.self_url = self_url;
+
+    match archive {
+        Some(mut archive) => archive_feed(feed, &mut archive, &requested.archive_key, may_store),
+        None => Ok(feed),
+    }
+}
+
+fn make_request(request: &Request) -> anyhow::Result<Requested> {
+    if let Some(token) = feed_token(request) {
+        let secret = env::var(TOKEN_SECRET_VARIABLE).map_err(|_| anyhow::anyhow!("Feed tokens require {} to be set", TOKEN_SECRET_VARIABLE))?;
+        let builder = token_feed_request_builder(token, secret.as_bytes())?;
+
+        return Ok(Requested {
+            archive_key: Archive::key(builder.to_query_pairs()),
+            feed: RequestedFeed::Single(Box::new(builder.build()?)),
+            is_signed: true,
+        });
+    }
+
+    if env::var(REQUIRE_TOKEN_VARIABLE).map(|value| value == "true").unwrap_or(false) {
+        return Err(Forbidden("Feeds must be requested with a token, see `mk-rss-cli to-rss-url --token`".into()).into());
+    }
+
+    if is_aggregate_request(request) {
+        let pairs = query_pairs(request).into_iter().filter(|(name, _)| name != "archive_page").collect();
+        let mut feed = RequestedFeed::Aggregate(make_aggregate_request(request)?);
+        feed.limit_hosts();
+
+        return Ok(Requested { archive_key: Archive::key(pairs), feed, is_signed: false });
+    }
+
+    let builder = FeedRequestBuilder::from_query_pairs(query_pairs(request))?;
+    let mut feed = RequestedFeed::Single(Box::new(builder.build()?));
+    feed.limit_hosts();
+
+    Ok(Requested { archive_key: Archive::key(builder.to_query_pairs()), feed, is_signed: false })
+}
+
+/// Archive the items in `feed` under `archive_key` if we `may_store` them, then link it to its
+/// archive pages as described by RFC 5005.
+///
+/// The feed is filled with every archived item that isn't on a complete archive page yet, so
+/// together with those pages it covers the whole archive. Links are built from the `self_url`
+/// of `feed`, without which we can't link anywhere.
+fn archive_feed(mut feed: Feed, archive: &mut Archive, archive_key: &str, may_store: bool) -> anyhow::Result<Feed> {
+    if may_store {
+        archive.store(archive_key, &feed, Local::now())?;
+    }
+
+    let current_guids: HashSet<String> = feed.items.iter().map(|item| item.guid.value.clone()).collect();
+    feed.items.extend(
+        archive
+            .incomplete_page(archive_key, ARCHIVE_PAGE_SIZE)?
+            .into_iter()
+            .filter(|item| !current_guids.contains(&item.guid.value))
+    );
+    feed.items.sort_by_key(|item| cmp::Reverse(item.pub_date));
+
+    let self_url = match &feed.self_url {
+        Some(self_url) => self_url.clone(),
+        None => return Ok(feed),
+    };
+
+    let page_count = archive.archive_page_count(archive_key, ARCHIVE_PAGE_SIZE)?;
+    if page_count > 0 {
+        feed.archive_links.prev_archive = Some(with_archive_page(&self_url, Some(page_count)));
+    }
+
+    Ok(feed)
+}
+
+/// `feed` holding page `archive_page` of its archive under `archive_key`, linked to the pages
+/// either side of it and to the current feed
+fn archive_page_feed(feed: Feed, archive: &Archive, archive_key: &str, archive_page: &str) -> anyhow::Result<Feed> {
+    let page = archive_page.parse::<usize>().context("archive_page must be a number")?;
+    let items = archive.archive_page(archive_key, page, ARCHIVE_PAGE_SIZE)?;
+    let page_count = archive.archive_page_count(archive_key, ARCHIVE_PAGE_SIZE)?;
+
+    let archive_links = match &feed.self_url {
+        Some(self_url) => {
+            let page_url = |page: usize| with_archive_page(self_url, Some(page));
+
+            ArchiveLinks {
+                is_archive: true,
+                current: Some(with_archive_page(self_url, None)),
+                prev_archive: Some(page - 1).filter(|&page| page > 0).map(page_url),
+                next_archive: Some(page + 1).filter(|&page| page <= page_count).map(page_url),
+            }
+        },
+        None => ArchiveLinks { is_archive: true, ..ArchiveLinks::default() },
+    };
+
+    let last_build_date = items.iter().map(|item| item.pub_date).max().unwrap_or(feed.last_build_date);
+
+    Ok(Feed { items, archive_links, last_build_date, ..feed })
+}
+
+/// `url` with its `archive_page` parameter set to `page`, or removed if there isn't one
+fn with_archive_page(url: &Url, page: Option<usize>) -> Url {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| name != "archive_page")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+
+    let mut url = url.clone();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+
+    if let Some(page) = page {
+        url.query_pairs_mut().append_pair("archive_page", &page.to_string());
+    }
+
+    url
+}
+
+/// The token of a request for `/f/<token>`, if it is one
+fn feed_token(request: &Request) -> Option<&str> {
+    request
+        .uri()
+        .path()
+        .rsplit_once("/f/")
+        .map(|(_, token)| token.trim_end_matches('/'))
+        .filter(|token| !token.is_empty())
+}
+
+fn token_feed_request_builder(token: &str, secret: &[u8]) -> anyhow::Result<FeedRequestBuilder> {
+    let builder = FeedRequestBuilder::from_token(token, secret).map_err(|e| Forbidden(e.to_string()))?;
+
+    Ok(builder)
+}
+
+/// An aggregate request names the feed with `name` and `url` and lists each feed to merge as a
+/// `feed` parameter, holding either an mk-rss url or just its query string.
+fn make_aggregate_request(request: &Request) -> anyhow::Result<AggregateRequest> {
     let params = request.query_string_parameters();
 
     let get_required = |name: &str| -> anyhow::Result<String> {
@@ -46,40 +304,62 @@ fn make_feed_request(request: Request) -> anyhow::Result<FeedRequest> {
 
     let name = get_required("name")?;
     let url = get_required("url").and_then(|s| Url::parse(&s).context("Could not parse URL"))?;
-    let item_selector = get_required("item_selector")?;
-
-    let max_items = params
-        .get("max_items")
-        .map(|s| s.parse::<usize>().context("max_items must be a number"))
-        .transpose()?;
-
-    let order = params
-        .get("order")
-        .map(|s| FeedOrder::try_from(s))
-        .transpose()?;
-
-    let feed_request_builder = FeedRequestBuilder {
-        name,
-        url,
-        item_selector,
-        title_selector: params.get("title_selector").map(|s| s.to_string()),
-        link_selector: params.get("link_selector").map(|s| s.to_string()),
-        pub_date_selector: params.get("pub_date_selector").map(|s| s.to_string()),
-        max_items,
-        order
-    };
 
-    let feed_request = feed_request_builder.build()?;
+    let definitions = params.get_all("feed").unwrap_or_default();
+    if definitions.len() > MAX_AGGREGATE_FEEDS {
+        return Err(anyhow::anyhow!("An aggregate feed can merge at most {} feeds", MAX_AGGREGATE_FEEDS));
+    }
+
+    let feeds = definitions
+        .into_iter()
+        .map(|definition| {
+            FeedRequestBuilder::from_definition(definition)
+                .and_then(|builder| builder.build())
+                .with_context(|| format!("Could not parse feed {}", definition))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut aggregate_request = AggregateRequest::new(&name, url, feeds);
 
-    Ok(feed_request)
+    if let Some(source_tag) = params.get("source_tag") {
+        aggregate_request.source_tag = SourceTag::try_from(source_tag)?;
+    }
+
+    if let Some(max_items) = params.get("max_items") {
+        let max_items = max_items.parse::<usize>().context("max_items must be a number")?;
+        aggregate_request.max_items = cmp::min(max_items, aggregate_request.max_items);
+    }
+
+    Ok(aggregate_request)
+}
+
+fn is_aggregate_request(request: &Request) -> bool {
+    request.query_string_parameters().get("feed").is_some()
+}
+
+/// Every query parameter of `request`, including repeated ones
+fn query_pairs(request: &Request) -> Vec<(String, String)> {
+    let params = request.query_string_parameters();
+
+    params
+        .iter()
+        .flat_map(|(name, _)| {
+            params
+                .get_all(name)
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |value| (name.to_string(), value.to_string()))
+        })
+        .collect()
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mk_rss::FeedRequest;
+    use mk_rss::{FeedItem, HostLimits, FeedOrder, GuidStrategy, SourceKind};
     use netlify_lambda_http::{Request, RequestExt};
+    use chrono::{Duration, TimeZone};
     use itertools::Itertools;
 
     #[test]
@@ -91,8 +371,27 @@ mod tests {
             ("title_selector", ".title-class"),
             ("link_selector", ".link-class"),
             ("pub_date_selector", ".pub-date-class"),
+            ("description_selector", ".description-class"),
+            ("image_selector", "img"),
+            ("enclosure_selector", "audio"),
+            ("author_selector", ".byline"),
+            ("category_selector", ".tags a"),
+            ("source", "html"),
             ("order", "reversed"),
             ("max_items", "25"),
+            ("title_rewrite", "Read more »=>"),
+            ("title_rewrite", r"\s+\| Example$=>"),
+            ("strip_query_param", "utm_*"),
+            ("host_rewrite", "example.com=>m.example.com"),
+            ("guid_strategy", "attribute:data-id"),
+            ("feed_description", "Everything new at Example"),
+            ("language", "en-AU"),
+            ("image", "/logo.png"),
+            ("ttl", "120"),
+            ("max_concurrent_requests", "1"),
+            ("request_interval_ms", "2000"),
+            ("respect_robots_txt", "true"),
+            ("encoding", "shift_jis"),
         ];
 
         let params = params
@@ -107,16 +406,222 @@ mod tests {
             .title_selector(".title-class")
             .link_selector(".link-class")
             .pub_date_selector(".pub-date-class")
+            .description_selector(".description-class")
+            .image_selector("img")
+            .enclosure_selector("audio")
+            .author_selector(".byline")
+            .category_selector(".tags a")
+            .source(SourceKind::Html)
             .order(FeedOrder::Reversed)
-            .max_items(25 as usize)
+            .max_items(25_usize)
+            .title_rewrite("Read more »=>")
+            .title_rewrite(r"\s+\| Example$=>")
+            .strip_query_param("utm_*")
+            .host_rewrite("example.com=>m.example.com")
+            .guid_strategy(GuidStrategy::Attribute("data-id".into()))
+            .feed_description("Everything new at Example")
+            .language("en-AU")
+            .image("/logo.png")
+            .ttl(120)
+            .max_concurrent_requests(1)
+            .request_interval_ms(2000)
+            .respect_robots_txt(true)
+            .encoding("shift_jis")
             .build()
             .unwrap();
 
-        let expected: anyhow::Result<FeedRequest, String> = Ok(expected);
+        let expected: anyhow::Result<RequestedFeed, String> = Ok(RequestedFeed::Single(Box::new(expected)));
 
-        let feed_request = make_feed_request(request)
+        let feed_request = make_request(&request)
+            .map(|requested| requested.feed)
             .map_err(|e| format!("{}", e));
 
         assert_eq!(feed_request, expected);
     }
+
+    #[test]
+    pub fn parse_xpath_selectors() {
+        let params = vec![
+            ("name", "Example RSS"),
+            ("url", "https://example.com/feed"),
+            ("item_selector", "xpath://article"),
+            ("link_selector", "xpath:.//a[@rel='bookmark']"),
+        ];
+
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into_group_map();
+
+        let request = Request::default()
+            .with_query_string_parameters(params);
+
+        let expected = FeedRequestBuilder::new("Example RSS", Url::parse("https://example.com/feed").unwrap(), "xpath://article")
+            .link_selector("xpath:.//a[@rel='bookmark']")
+            .build()
+            .unwrap();
+
+        let feed_request = match make_request(&request).unwrap().feed {
+            RequestedFeed::Single(feed_request) => feed_request,
+            RequestedFeed::Aggregate(_) => panic!("expected a single feed"),
+        };
+
+        assert_eq!(feed_request.source, expected.source);
+    }
+
+    #[test]
+    pub fn unsigned_requests_should_not_loosen_host_limits() {
+        let params = vec![
+            ("name", "Example RSS"),
+            ("url", "https://example.com/feed"),
+            ("item_selector", ".class"),
+            ("max_concurrent_requests", "100000"),
+            ("request_interval_ms", "0"),
+            ("respect_robots_txt", "false"),
+        ];
+
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into_group_map();
+
+        let request = Request::default()
+            .with_query_string_parameters(params);
+
+        env::set_var("MK_RSS_RESPECT_ROBOTS_TXT", "true");
+        let feed_request = match make_request(&request).unwrap().feed {
+            RequestedFeed::Single(feed_request) => feed_request,
+            RequestedFeed::Aggregate(_) => panic!("expected a single feed"),
+        };
+        env::remove_var("MK_RSS_RESPECT_ROBOTS_TXT");
+
+        assert_eq!(feed_request.host_limits.respect_robots_txt, Some(true));
+
+        let defaults = HostLimits::default().no_looser_than_defaults();
+        assert_eq!(feed_request.host_limits.max_concurrent_requests, defaults.max_concurrent_requests);
+        assert_eq!(feed_request.host_limits.request_interval, defaults.request_interval);
+    }
+
+    #[test]
+    pub fn parse_aggregate_request() {
+        let params = vec![
+            ("name", "Team reading"),
+            ("url", "https://team.example.com/"),
+            ("feed", "https://mk-rss.example.com/?name=Alice&url=https%3A%2F%2Falice.example.com%2F&item_selector=article"),
+            ("feed", "name=Bob&url=https%3A%2F%2Fbob.example.com%2Ffeed.xml&source=feed"),
+            ("source_tag", "source"),
+            ("max_items", "50"),
+        ];
+
+        let params = params
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .into_group_map();
+
+        let request = Request::default()
+            .with_query_string_parameters(params);
+
+        assert!(is_aggregate_request(&request));
+
+        let alice = FeedRequestBuilder::new("Alice", Url::parse("https://alice.example.com/").unwrap(), "article")
+            .build()
+            .unwrap();
+
+        let mut bob = FeedRequestBuilder::new("Bob", Url::parse("https://bob.example.com/feed.xml").unwrap(), "");
+        bob.item_selector = None;
+        let bob = bob.source(SourceKind::Feed).build().unwrap();
+
+        let mut expected = AggregateRequest::new("Team reading", Url::parse("https://team.example.com/").unwrap(), vec![alice, bob]);
+        expected.source_tag = SourceTag::Source;
+
+        assert_eq!(make_aggregate_request(&request).map_err(|e| format!("{}", e)), Ok(expected));
+    }
+
+    #[test]
+    pub fn aggregate_requests_should_merge_a_limited_number_of_feeds() {
+        let mut params = vec![
+            ("name".to_string(), "Team reading".to_string()),
+            ("url".to_string(), "https://team.example.com/".to_string()),
+        ];
+        for i in 0..=MAX_AGGREGATE_FEEDS {
+            params.push(("feed".into(), format!("name=Feed{}&url=https%3A%2F%2Fexample.com%2F{}&item_selector=article", i, i)));
+        }
+
+        let request = Request::default()
+            .with_query_string_parameters(params.into_iter().into_group_map());
+
+        assert!(make_aggregate_request(&request).is_err());
+    }
+
+    #[test]
+    pub fn parse_token_request() {
+        let expected = FeedRequestBuilder::new("Example RSS", Url::parse("https://example.com/feed").unwrap(), ".class")
+            .title_selector(".title-class")
+            .build()
+            .unwrap();
+
+        let mut builder = FeedRequestBuilder::new("Example RSS", Url::parse("https://example.com/feed").unwrap(), ".class");
+        let token = builder.title_selector(".title-class").to_token(b"secret");
+
+        let mut request = Request::default();
+        *request.uri_mut() = format!("https://mk-rss.example.com/.netlify/functions/mk-rss/f/{}?archive_page=1", token).parse().unwrap();
+
+        assert_eq!(feed_token(&request), Some(token.as_str()));
+        let builder = token_feed_request_builder(&token, b"secret").unwrap();
+        assert_eq!(builder.build().map_err(|e| format!("{}", e)), Ok(expected));
+        let error = token_feed_request_builder(&token, b"another secret").unwrap_err();
+        assert!(error.is::<Forbidden>());
+        assert_eq!(feed_token(&Request::default()), None);
+    }
+
+    #[test]
+    pub fn archive_feed_should_link_archive_pages() {
+        let feed = |paths: std::ops::Range<usize>, archive_page: Option<&str>| {
+            let items = paths
+                .rev()
+                .map(|path| FeedItem {
+                    pub_date: Local.ymd(2021, 1, 1).and_hms(0, 0, 0) + Duration::minutes(path as i64),
+                    ..FeedItem::for_test(&format!("Story {}", path), &format!("https://example.com/{}", path))
+                })
+                .collect();
+
+            let mut self_url = Url::parse("https://mk-rss.example.com/?name=Example").unwrap();
+            if let Some(archive_page) = archive_page {
+                self_url.query_pairs_mut().append_pair("archive_page", archive_page);
+            }
+
+            Feed { last_build_date: Local::now(), self_url: Some(self_url), ..Feed::for_test("Example", items) }
+        };
+
+        let mut archive = Archive::open_in_memory().unwrap();
+        let page_url = |page: &str| Some(Url::parse(&format!("https://mk-rss.example.com/?name=Example{}", page)).unwrap());
+
+        let current = archive_feed(feed(0..20, None), &mut archive, "example", true).unwrap();
+        assert_eq!(current.archive_links, ArchiveLinks::default());
+
+        let current = archive_feed(feed(100..120, None), &mut archive, "example", false).unwrap();
+        assert_eq!(current.archive_links, ArchiveLinks::default());
+        assert_eq!(current.items.len(), 40);
+
+        let current = archive_feed(feed(20..70, None), &mut archive, "example", true).unwrap();
+        assert_eq!(current.archive_links.prev_archive, page_url("&archive_page=2"));
+
+        // Items that dropped off the page but aren't on a complete archive page yet stay in the feed
+        let current = archive_feed(feed(65..70, None), &mut archive, "example", true).unwrap();
+        assert_eq!(current.items.len(), 10);
+        assert_eq!(current.items[9].title, "Story 60");
+
+        let first_page = archive_page_feed(feed(0..0, Some("1")), &archive, "example", "1").unwrap();
+        assert_eq!(first_page.items.len(), ARCHIVE_PAGE_SIZE);
+        assert_eq!(first_page.items[0].title, "Story 29");
+        assert_eq!(first_page.last_build_date, first_page.items[0].pub_date);
+        assert_eq!(first_page.archive_links, ArchiveLinks {
+            is_archive: true,
+            current: page_url(""),
+            prev_archive: None,
+            next_archive: page_url("&archive_page=2"),
+        });
+
+        assert!(archive_page_feed(feed(0..0, Some("3")), &archive, "example", "3").is_err());
+    }
 }